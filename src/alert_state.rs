@@ -0,0 +1,127 @@
+use crate::api::alert_rules::AlertRuleGroup;
+use chrono::{DateTime, Local};
+use log::debug;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+type SetName  = String;
+type GroupKey = (String, String);
+
+// Fields which are specific to the instance a rule lives on
+const INSTANCE_FIELDS: &[&str] = &["id", "orgID", "folderUID", "updated", "provenance"];
+
+/// One instance's copy of a rule group. The folder is known by its title, because folder
+/// UIDs differ between the instances.
+#[derive(Debug, Clone)]
+pub struct AlertGroupCopy {
+    pub folder_title: String,
+    pub group: AlertRuleGroup,
+    pub updated: Option<DateTime<Local>>,
+}
+
+impl AlertGroupCopy {
+    /// Strips everything instance specific from the exported group, so copies can be compared.
+    pub fn new(folder_title: String, mut group: AlertRuleGroup) -> Self {
+        let updated = group
+            .rules
+            .iter()
+            .filter_map(|r| r.get("updated")?.as_str()?.parse::<DateTime<Local>>().ok())
+            .max();
+
+        group.folder_uid.clear();
+        for rule in &mut group.rules {
+            if let Value::Object(obj) = rule {
+                INSTANCE_FIELDS.iter().for_each(|f| {
+                    obj.remove(*f);
+                });
+            }
+        }
+        group.rules.sort_by(|a, b| rule_uid(a).cmp(rule_uid(b)));
+
+        Self {
+            folder_title,
+            group,
+            updated,
+        }
+    }
+
+    fn key(&self) -> GroupKey {
+        (self.folder_title.clone(), self.group.title.clone())
+    }
+}
+
+/// A rule group which has to be written to some instances.
+#[derive(Debug, Clone)]
+pub struct AlertUpdate<'a> {
+    pub source: &'a AlertGroupCopy,
+    pub targets: Vec<&'a str>,
+}
+
+/// Copies of the synced alert rule groups, per instance.
+#[derive(Debug, Clone, Default)]
+pub struct AlertState {
+    sets: HashMap<SetName, Vec<AlertGroupCopy>>,
+}
+
+impl AlertState {
+
+    /* Constructors */
+
+    pub fn add_set(&mut self, base_url: String, groups: Vec<AlertGroupCopy>) {
+        self.sets.insert(base_url, groups);
+    }
+
+    /* public API */
+
+    /// Picks the most recently updated copy of every rule group and lists the instances
+    /// where it's missing or different. Rule groups are never deleted.
+    pub fn diff(&self) -> Vec<AlertUpdate<'_>> {
+        let mut by_key: HashMap<GroupKey, Vec<(&str, &AlertGroupCopy)>> = HashMap::new();
+        for (name, groups) in &self.sets {
+            for g in groups {
+                by_key.entry(g.key()).or_default().push((name.as_str(), g));
+            }
+        }
+
+        by_key
+            .into_iter()
+            .filter_map(|((folder, title), copies)| {
+                let (_, newest) = copies.iter().copied().max_by_key(|(_, g)| g.updated)?;
+
+                let targets = self
+                    .sets
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|name| {
+                        !copies
+                            .iter()
+                            .any(|(n, g)| n == name && g.group == newest.group)
+                    })
+                    .collect::<Vec<_>>();
+
+                debug!("alert group {folder}/{title}: {} outdated copies", targets.len());
+                (!targets.is_empty()).then_some(AlertUpdate {
+                    source: newest,
+                    targets,
+                })
+            })
+            .collect()
+    }
+
+    pub fn unique_folders(&self) -> HashSet<&str> {
+        self.sets
+            .values()
+            .flat_map(|v| v.iter().map(|g| g.folder_title.as_str()))
+            .collect()
+    }
+
+    pub fn print_data_stats(&self) {
+        for (name, groups) in &self.sets {
+            debug!("{name}: {} sync alert rule groups", groups.len());
+        }
+    }
+}
+
+fn rule_uid(rule: &Value) -> &str {
+    rule.get("uid").and_then(Value::as_str).unwrap_or_default()
+}
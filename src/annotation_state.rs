@@ -0,0 +1,218 @@
+use crate::api::annotations::{Annotation, AnnotationBody};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+type SetName = String;
+type Origin = String;
+
+/// An annotation's copy on one instance, known by the annotation's origin.
+pub type KnownCopy = (Origin, SetName);
+
+/// Tag marking a replicated annotation with the instance and id of its original.
+pub const ORIGIN_TAG_PREFIX: &str = "grafana-sync-origin:";
+
+/// A change to one instance's annotations.
+#[derive(Debug, Clone)]
+pub enum AnnotationChange<'a> {
+    Create {
+        target: &'a str,
+        body: AnnotationBody,
+    },
+    Update {
+        target: &'a str,
+        id: i64,
+        body: AnnotationBody,
+    },
+    Delete {
+        target: &'a str,
+        id: i64,
+    },
+}
+
+/// The annotations of one synced dashboard on all instances.
+///
+/// Every annotation is known by its origin: `<base url>#<id>` of the original, which the copies
+/// carry as a tag. An annotation whose original is gone, or whose copy on an instance existed
+/// in the last cycle but is missing now, was deleted and is deleted everywhere. Instances which
+/// never had a copy, e.g. newly added ones, get one.
+#[derive(Debug, Clone)]
+pub struct AnnotationState {
+    dashboard_uid: String,
+    sets: HashMap<SetName, Vec<Annotation>>,
+}
+
+impl AnnotationState {
+    /* Constructors */
+
+    pub fn new(dashboard_uid: String) -> Self {
+        Self {
+            dashboard_uid,
+            sets: HashMap::new(),
+        }
+    }
+
+    pub fn add_set(&mut self, base_url: String, annotations: Vec<Annotation>) {
+        self.sets.insert(base_url, annotations);
+    }
+
+    /* public API */
+
+    /// Lists the changes which bring all instances in line, and the copies that will then
+    /// exist. The most recently updated copy of an annotation wins.
+    pub fn diff(
+        &self,
+        known: &HashSet<KnownCopy>,
+    ) -> (Vec<AnnotationChange<'_>>, HashSet<KnownCopy>) {
+        let mut by_origin: HashMap<Origin, Vec<(&str, &Annotation)>> = HashMap::new();
+        for (name, annotations) in &self.sets {
+            for a in annotations {
+                by_origin
+                    .entry(origin_of(name, a))
+                    .or_default()
+                    .push((name.as_str(), a));
+            }
+        }
+
+        let mut changes = Vec::new();
+        let mut complete = HashSet::new();
+
+        for (origin, copies) in by_origin {
+            // copies are never turned back into an original
+            let original_gone = self
+                .sets
+                .keys()
+                .find(|name| is_origin(name, &origin))
+                .is_some_and(|name| !copies.iter().any(|(n, _)| n == name));
+            let copy_gone = self.sets.keys().any(|name| {
+                !copies.iter().any(|(n, _)| n == name)
+                    && known.contains(&(origin.clone(), name.clone()))
+            });
+
+            if original_gone || copy_gone {
+                debug!("annotation {origin} was deleted, deleting all copies");
+                changes.extend(copies.iter().map(|(name, a)| AnnotationChange::Delete {
+                    target: name,
+                    id: a.id,
+                }));
+                continue;
+            }
+
+            let Some(&(_, newest)) = copies.iter().max_by_key(|(_, a)| a.updated) else {
+                continue;
+            };
+            for name in self.sets.keys() {
+                let body = self.body_for(name, &origin, newest);
+                match copies.iter().find(|(n, _)| n == name) {
+                    None => changes.push(AnnotationChange::Create { target: name, body }),
+                    Some((_, a)) if self.body_for(name, &origin, a) != body => {
+                        changes.push(AnnotationChange::Update {
+                            target: name,
+                            id: a.id,
+                            body,
+                        })
+                    }
+                    Some(_) => {}
+                }
+                complete.insert((origin.clone(), name.clone()));
+            }
+        }
+
+        (changes, complete)
+    }
+
+    /// How `annotation` looks on `instance`: the original has no origin tag, copies do.
+    fn body_for(&self, instance: &str, origin: &str, annotation: &Annotation) -> AnnotationBody {
+        let mut tags = annotation
+            .tags
+            .iter()
+            .filter(|t| !t.starts_with(ORIGIN_TAG_PREFIX))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !is_origin(instance, origin) {
+            tags.push(format!("{ORIGIN_TAG_PREFIX}{origin}"));
+        }
+        tags.sort();
+
+        AnnotationBody {
+            dashboard_uid: self.dashboard_uid.clone(),
+            panel_id: annotation.panel_id,
+            time: annotation.time,
+            time_end: annotation.time_end,
+            text: annotation.text.clone(),
+            tags,
+        }
+    }
+}
+
+fn origin_of(instance: &str, annotation: &Annotation) -> Origin {
+    annotation
+        .tags
+        .iter()
+        .find_map(|t| t.strip_prefix(ORIGIN_TAG_PREFIX))
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{instance}#{}", annotation.id))
+}
+
+fn is_origin(instance: &str, origin: &str) -> bool {
+    origin
+        .strip_prefix(instance)
+        .is_some_and(|id| id.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "https://a.example";
+    const B: &str = "https://b.example";
+    const C: &str = "https://c.example";
+
+    fn annotation(id: i64, tags: &[&str]) -> Annotation {
+        Annotation {
+            id,
+            panel_id: 0,
+            time: 1,
+            time_end: 1,
+            text: "deploy".to_owned(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            updated: 1,
+        }
+    }
+
+    fn known(copies: &[(&str, &str)]) -> HashSet<KnownCopy> {
+        copies
+            .iter()
+            .map(|(origin, name)| (origin.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn added_instance_gets_copies_instead_of_deleting() {
+        let origin = format!("{A}#1");
+        let mut state = AnnotationState::new("dash".to_owned());
+        state.add_set(A.to_owned(), vec![annotation(1, &[])]);
+        let copy = annotation(7, &[&format!("{ORIGIN_TAG_PREFIX}{origin}")]);
+        state.add_set(B.to_owned(), vec![copy]);
+        state.add_set(C.to_owned(), vec![]);
+
+        let (changes, present) = state.diff(&known(&[(&origin, A), (&origin, B)]));
+
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], AnnotationChange::Create { target: C, .. }));
+        assert_eq!(present, known(&[(&origin, A), (&origin, B), (&origin, C)]));
+    }
+
+    #[test]
+    fn missing_known_copy_deletes_everywhere() {
+        let origin = format!("{A}#1");
+        let mut state = AnnotationState::new("dash".to_owned());
+        state.add_set(A.to_owned(), vec![annotation(1, &[])]);
+        state.add_set(B.to_owned(), vec![]);
+
+        let (changes, present) = state.diff(&known(&[(&origin, A), (&origin, B)]));
+
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], AnnotationChange::Delete { target: A, id: 1 }));
+        assert!(present.is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use chrono::{DateTime, Local};
+use log::{debug, info};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Everything but the unreserved characters of RFC 3986, group titles and the names of
+// templates and mute timings may contain anything
+pub(crate) const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The few fields of a provisioned alert rule needed to select and group it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRuleSummary {
+    pub uid: String,
+    pub title: String,
+    #[serde(rename = "folderUID")]
+    pub folder_uid: String,
+    pub rule_group: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    pub updated: Option<DateTime<Local>>,
+}
+
+/// A rule group as exported and imported by the provisioning API. The rules are kept
+/// as raw JSON so that no field gets lost on the way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRuleGroup {
+    pub title: String,
+    pub folder_uid: String,
+    pub interval: i64,
+    pub rules: Vec<serde_json::Value>,
+}
+
+impl GrafanaInstance {
+    pub async fn get_alert_rules(&self) -> Result<Vec<AlertRuleSummary>, GSError> {
+        let endpoint = format!("{}/api/v1/provisioning/alert-rules", &self.base_url());
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<AlertRuleSummary>>(&text)?)
+    }
+
+    fn rule_group_endpoint(&self, folder_uid: &str, group: &str) -> String {
+        format!(
+            "{}/api/v1/provisioning/folder/{}/rule-groups/{}",
+            &self.base_url(),
+            utf8_percent_encode(folder_uid, PATH_SEGMENT),
+            utf8_percent_encode(group, PATH_SEGMENT)
+        )
+    }
+
+    pub async fn get_alert_rule_group(
+        &self,
+        folder_uid: &str,
+        group: &str,
+    ) -> Result<AlertRuleGroup, GSError> {
+        let endpoint = self.rule_group_endpoint(folder_uid, group);
+        let client = self.client();
+
+        debug!("Requesting alert rule group {folder_uid}/{group}");
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<AlertRuleGroup>(&text)?)
+    }
+
+    /// Creates or replaces a whole rule group. Provenance is disabled so that the rules
+    /// stay editable in the UI, which is where changes are expected to come from.
+    pub async fn put_alert_rule_group(&self, group: &AlertRuleGroup) -> Result<(), GSError> {
+        let endpoint = self.rule_group_endpoint(&group.folder_uid, &group.title);
+        let client = self.client();
+
+        let request = client
+            .put(endpoint)
+            .header("X-Disable-Provenance", "true")
+            .json(group);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of alert rule group \"{}\" to {} successful",
+            group.title,
+            self.name()
+        );
+        Ok(())
+    }
+}
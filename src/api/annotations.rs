@@ -0,0 +1,116 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+// Most annotations fetched per dashboard and cycle
+pub const ANNOTATION_LIMIT: usize = 1000;
+
+/// A manual annotation on a dashboard. Times are epoch milliseconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    pub id: i64,
+    #[serde(default)]
+    pub panel_id: i64,
+    pub time: i64,
+    #[serde(default)]
+    pub time_end: i64,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub updated: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationBody {
+    #[serde(rename = "dashboardUID")]
+    pub dashboard_uid: String,
+    pub panel_id: i64,
+    pub time: i64,
+    pub time_end: i64,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+impl GrafanaInstance {
+    /// Manual annotations of a dashboard between `from` and `to`, alert annotations excluded.
+    pub async fn get_annotations(
+        &self,
+        dashboard_uid: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Annotation>, GSError> {
+        let endpoint = format!("{}/api/annotations", &self.base_url());
+        let client = self.client();
+
+        debug!("Requesting annotations of dashboard: {}", dashboard_uid);
+
+        let request = client
+            .get(endpoint)
+            .query(&[
+                ("dashboardUID", dashboard_uid),
+                ("type", "annotation"),
+                ("from", &from.to_string()),
+                ("to", &to.to_string()),
+                ("limit", &ANNOTATION_LIMIT.to_string()),
+            ]);
+        let response = self.send(request)
+            .await?
+            .error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<Annotation>>(&text)?)
+    }
+
+    pub async fn create_annotation(&self, body: &AnnotationBody) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/annotations", &self.base_url());
+        let client = self.client();
+
+        let request = client
+            .post(endpoint)
+            .json(body);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of annotation on dashboard '{}' to {} successful",
+            body.dashboard_uid,
+            self.name()
+        );
+        Ok(())
+    }
+
+    pub async fn update_annotation(&self, id: i64, body: &AnnotationBody) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/annotations/{}", &self.base_url(), id);
+        let client = self.client();
+
+        let request = client
+            .put(endpoint)
+            .json(body);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Update of annotation {id} on dashboard '{}' on {} successful",
+            body.dashboard_uid,
+            self.name()
+        );
+        Ok(())
+    }
+
+    pub async fn delete_annotation(&self, id: i64) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/annotations/{}", &self.base_url(), id);
+        let client = self.client();
+
+        self.send(client.delete(endpoint)).await?.error_for_status()?;
+
+        info!("Deleted annotation {id} on {}", self.name());
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
 use crate::error::GSError;
 use crate::instance::GrafanaInstance;
+use crate::selector::Selector;
 use chrono::{DateTime, Local};
 use log::{debug, info, warn};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use tokio::sync::RwLock;
 use tracing::instrument;
 
+pub(crate) const SEARCH_PAGE_SIZE: usize = 1000;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Tag {
     pub term: String,
@@ -30,6 +35,19 @@ pub struct SimpleDashboard {
     pub folder_title: Option<String>,
     pub folder_url: Option<String>,
     pub sort_meta: u32,
+    // only listed by some Grafana versions, they tell whether a cached copy is still current
+    #[serde(default)]
+    pub version: Option<i64>,
+    #[serde(default)]
+    pub updated: Option<DateTime<Local>>,
+}
+
+/// Result of listing an instance through a [`Selector`].
+#[derive(Debug, Clone, Default)]
+pub struct DashboardListing {
+    pub selected: Vec<SimpleDashboard>,
+    // dashboards which exist on the instance but aren't selected
+    pub unselected_uids: HashSet<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -80,6 +98,46 @@ pub struct FullDashboardData {
     pub uid: String,
     pub version: i32,
     pub week_start: String,
+    // everything else in the model (refresh, liveNow, ...), kept so it survives the import
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl FullDashboardData {
+    /// Calls `f` for all panels, including those nested in (collapsed) rows.
+    pub fn for_each_panel<'a>(&'a self, mut f: impl FnMut(&'a serde_json::Value)) {
+        fn walk<'a>(panels: &'a [serde_json::Value], f: &mut impl FnMut(&'a serde_json::Value)) {
+            for panel in panels {
+                f(panel);
+                if let Some(nested) = panel.get("panels").and_then(|p| p.as_array()) {
+                    walk(nested, f);
+                }
+            }
+        }
+        walk(&self.panels, &mut f);
+    }
+
+    pub fn panel_types(&self) -> HashSet<&str> {
+        let mut types = HashSet::new();
+        self.for_each_panel(|panel| {
+            types.extend(panel.get("type").and_then(|t| t.as_str()));
+        });
+        types
+    }
+
+    /// UIDs of the library panels the dashboard uses.
+    pub fn library_panel_uids(&self) -> HashSet<&str> {
+        let mut uids = HashSet::new();
+        self.for_each_panel(|panel| {
+            uids.extend(
+                panel
+                    .get("libraryPanel")
+                    .and_then(|l| l.get("uid"))
+                    .and_then(|u| u.as_str()),
+            );
+        });
+        uids
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -117,6 +175,43 @@ pub struct FullDashboard {
     pub meta: FullDashboardMeta,
 }
 
+impl FullDashboard {
+    /// The dashboard as a search would list it, e.g. to check it against a [`Selector`].
+    pub fn summary(&self) -> SimpleDashboard {
+        SimpleDashboard {
+            id: self.dashboard.extra.get("id").and_then(|id| id.as_u64()).unwrap_or(0) as u32,
+            uid: self.dashboard.uid.clone(),
+            title: self.dashboard.title.clone(),
+            uri: format!("db/{}", self.meta.slug),
+            url: self.meta.url.clone(),
+            slug: self.meta.slug.clone(),
+            type_name: "dash-db".to_owned(),
+            tags: self.dashboard.tags.clone(),
+            is_starred: false,
+            folder_id: self.meta.folder_id.map(|id| id as u32),
+            folder_uid: self.meta.folder_uid.clone(),
+            folder_title: self.meta.folder_title.clone(),
+            folder_url: self.meta.folder_url.clone(),
+            sort_meta: 0,
+            version: Some(self.meta.version),
+            updated: Some(self.meta.updated),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DashboardVersion {
+    pub version: i64,
+}
+
+// Grafana 11 wraps the version list in an object, older releases return a bare array
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DashboardVersions {
+    Paged { versions: Vec<DashboardVersion> },
+    Plain(Vec<DashboardVersion>),
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DashboardImportBody {
@@ -128,34 +223,146 @@ pub struct DashboardImportBody {
     // pub plugin_id: String,
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DashboardSaveBody {
+    pub dashboard: FullDashboardData,
+    pub folder_uid: Option<String>,
+    pub overwrite: bool,
+    pub message: String,
+}
+
 #[allow(dead_code)]
 impl GrafanaInstance {
     pub async fn get_tags(&self) -> Result<Vec<Tag>, GSError> {
         let endpoint = format!("{}/api/dashboards/tags", &self.base_url());
         let client = self.client();
 
-        let response = client.get(endpoint).send().await?.error_for_status()?;
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
         let text = response.text().await?;
 
         Ok(serde_json::from_str::<Vec<Tag>>(&text)?)
     }
 
+    /// Lists all dashboards carrying `tag`, page by page.
+    ///
+    /// The result is cross-checked against the tag's dashboard count, since a silently
+    /// truncated listing would make the missing dashboards look deleted.
     pub async fn get_dashboards_by_tag(&self, tag: &str) -> Result<Vec<SimpleDashboard>, GSError> {
         let endpoint = format!("{}/api/search", &self.base_url());
-        let client = self.client();
 
-        let response = client
-            .get(endpoint)
-            .query(&[("tag", tag), ("permission", "View"), ("sort", "alpha-asc")])
-            .send()
-            .await?
-            .error_for_status()?;
-        let text = response.text().await?;
+        let dashboards = self
+            .get_all_pages(
+                &endpoint,
+                &[
+                    ("tag", tag),
+                    ("type", "dash-db"),
+                    ("permission", "View"),
+                    ("sort", "alpha-asc"),
+                ],
+                SEARCH_PAGE_SIZE,
+                |d: &SimpleDashboard| &d.uid,
+            )
+            .await?;
+
+        self.verify_tag_counts(&endpoint, &dashboards, [tag])
+            .await?;
+
+        Ok(dashboards)
+    }
 
-        Ok(serde_json::from_str::<Vec<SimpleDashboard>>(&text)?)
+    /// Lists every dashboard on the instance and splits them by whether `selector` chose them.
+    pub async fn get_selected_dashboards(
+        &self,
+        selector: &Selector,
+    ) -> Result<DashboardListing, GSError> {
+        let endpoint = format!("{}/api/search", &self.base_url());
+
+        let dashboards = self
+            .get_all_pages(
+                &endpoint,
+                &[
+                    ("type", "dash-db"),
+                    ("permission", "View"),
+                    ("sort", "alpha-asc"),
+                ],
+                SEARCH_PAGE_SIZE,
+                |d: &SimpleDashboard| &d.uid,
+            )
+            .await?;
+
+        self.verify_tag_counts(&endpoint, &dashboards, selector.included_tags())
+            .await?;
+
+        let paths = self.get_folder_paths().await?;
+        let mut listing = DashboardListing::default();
+        for d in dashboards {
+            if selector.matches(&d, &paths.path_of(&d)) {
+                listing.selected.push(d);
+            } else {
+                listing.unselected_uids.insert(d.uid);
+            }
+        }
+        Ok(listing)
     }
 
-    #[allow(dead_code)]
+    /// Compares how many listed dashboards carry each tag with Grafana's own tag count.
+    /// A server capping the page size below ours ends the paging early, so fewer dashboards
+    /// than counted fail the listing, unless the instance is configured with `partial_view`:
+    /// then the token may not view all of them and a mismatch is only warned about.
+    async fn verify_tag_counts<'a>(
+        &self,
+        endpoint: &str,
+        listed: &[SimpleDashboard],
+        tags: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), GSError> {
+        let mut tags = tags.into_iter().peekable();
+        if tags.peek().is_none() {
+            return Ok(());
+        }
+
+        let partial_view = self.options().partial_view;
+        let counts = match self.get_tags().await {
+            Ok(counts) => counts,
+            Err(e) if partial_view => {
+                warn!("{}: could not list tag counts: {e}", self.name());
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        for tag in tags {
+            let expected = counts
+                .iter()
+                .find(|t| t.term == tag)
+                .map(|t| t.count as usize)
+                .unwrap_or(0);
+            let found = listed
+                .iter()
+                .filter(|d| d.tags.iter().any(|t| t == tag))
+                .count();
+
+            if found >= expected {
+                continue;
+            }
+            if !partial_view {
+                return Err(GSError::TruncatedListing(
+                    endpoint.to_owned(),
+                    format!(
+                        "found {found} of {expected} dashboards tagged '{tag}' \
+                         (missing View permission? see partial_view)"
+                    ),
+                ));
+            }
+            warn!(
+                "{}: found {found} of {expected} dashboards tagged '{tag}' \
+                 (missing View permission?)",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// The dashboards and subfolders directly inside a folder.
     pub async fn get_dashboards_in_folder(
         &self,
         folder_uid: &str,
@@ -163,14 +370,14 @@ impl GrafanaInstance {
         let endpoint = format!("{}/api/search", &self.base_url());
         let client = self.client();
 
-        let response = client
+        let request = client
             .get(endpoint)
             .query(&[
                 ("folderUIDs", folder_uid),
                 ("permission", "View"),
                 ("sort", "alpha-asc"),
-            ])
-            .send()
+            ]);
+        let response = self.send(request)
             .await?
             .error_for_status()?;
         let text = response.text().await?;
@@ -184,12 +391,75 @@ impl GrafanaInstance {
 
         debug!("Requesting full dashboard of uid: {}", uid);
 
-        let response = client.get(endpoint).send().await?.error_for_status()?;
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
         let text = response.text().await?;
 
         Ok(serde_json::from_str(&text)?)
     }
 
+    /// Like [`GrafanaInstance::get_dashboard_full`], but returns `None` if no dashboard
+    /// with that uid exists.
+    pub async fn find_dashboard_full(&self, uid: &str) -> Result<Option<FullDashboard>, GSError> {
+        let endpoint = format!("{}/api/dashboards/uid/{}", &self.base_url(), uid);
+        let client = self.client();
+
+        debug!("Looking up full dashboard of uid: {}", uid);
+
+        let response = self.send(client.get(endpoint)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response.error_for_status()?.text().await?;
+
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    /// Looks up only the current version number of a dashboard, which is much cheaper
+    /// than downloading its full model.
+    pub async fn get_dashboard_version(&self, uid: &str) -> Result<Option<i64>, GSError> {
+        let endpoint = format!("{}/api/dashboards/uid/{}/versions", &self.base_url(), uid);
+        let client = self.client();
+
+        let request = client
+            .get(endpoint)
+            .query(&[("limit", "1")]);
+        let response = self.send(request)
+            .await?
+            .error_for_status()?;
+        let text = response.text().await?;
+
+        let versions = match serde_json::from_str::<DashboardVersions>(&text)? {
+            DashboardVersions::Paged { versions } => versions,
+            DashboardVersions::Plain(versions) => versions,
+        };
+
+        Ok(versions.into_iter().map(|v| v.version).max())
+    }
+
+    /// Saves a changed dashboard back into the folder it already lives in.
+    pub async fn save_dashboard(&self, dashboard: &FullDashboard) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/dashboards/db", &self.base_url());
+        let client = self.client();
+
+        debug!("Saving dashboard with uid: {}", dashboard.dashboard.uid);
+
+        let body = DashboardSaveBody {
+            dashboard: dashboard.dashboard.clone(),
+            folder_uid: dashboard.meta.folder_uid.clone(),
+            overwrite: true,
+            message: "updated by grafana-sync".to_string(),
+        };
+
+        let request = client
+            .post(endpoint)
+            .json(&body);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub async fn delete_dashboard(&self, uid: &str) -> Result<(), GSError> {
         let endpoint = format!("{}/api/dashboards/uid/{}", &self.base_url(), uid,);
@@ -197,7 +467,7 @@ impl GrafanaInstance {
 
         debug!("Deleting dashboard with uid: {}", uid);
 
-        client.delete(endpoint).send().await?.error_for_status()?;
+        self.send(client.delete(endpoint)).await?.error_for_status()?;
 
         Ok(())
     }
@@ -238,11 +508,16 @@ impl GrafanaInstance {
 
         info!(
             "Starting replication of dashboard \"{}\" onto {}",
-            dashboard.meta.url, base_url
+            dashboard.meta.url,
+            self.name()
         );
 
+        let mut data = dashboard.dashboard.clone();
+        // The numeric id is specific to the source instance, the uid identifies the dashboard
+        data.extra.remove("id");
+
         let body = DashboardImportBody {
-            dashboard: dashboard.dashboard.clone(),
+            dashboard: data,
             folder_uid,
             inputs: vec![],
             overwrite,
@@ -251,7 +526,7 @@ impl GrafanaInstance {
         };
 
         let client = self.client();
-        let response = client.post(endpoint).json(&body).send().await?;
+        let response = self.send(client.post(endpoint).json(&body)).await?;
         let status = response.status();
 
         if status.as_u16() == 412 {
@@ -263,7 +538,8 @@ impl GrafanaInstance {
 
         info!(
             "Replication of dashboard {} to {} successful",
-            dashboard.meta.url, base_url
+            dashboard.meta.url,
+            self.name()
         );
 
         let text = response.text().await?;
@@ -0,0 +1,27 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Datasource {
+    pub id: u32,
+    pub uid: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+impl GrafanaInstance {
+    pub async fn get_datasources(&self) -> Result<Vec<Datasource>, GSError> {
+        let endpoint = format!("{}/api/datasources", &self.base_url());
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<Datasource>>(&text)?)
+    }
+}
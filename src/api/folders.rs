@@ -1,10 +1,16 @@
-use crate::api::dashboards::Folder;
+use crate::api::dashboards::{Folder, SimpleDashboard, SEARCH_PAGE_SIZE};
 use crate::error::GSError;
 use crate::instance::GrafanaInstance;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
 use log::{debug, info};
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
+const FOLDER_PAGE_SIZE: usize = 1000;
+const ROOT_FOLDER: &str = "General";
+
 #[derive(Debug, Clone, Serialize)]
 struct FolderBody {
     description: String,
@@ -12,35 +18,144 @@ struct FolderBody {
     uid: String,
 }
 
+/// Snapshot of an instance's folders and how many items each of them holds.
+/// Built once per cycle so that mirroring and replication don't have to ask Grafana
+/// again for every single folder, and purging only re-checks the empty ones.
+#[derive(Debug, Clone, Default)]
+pub struct FolderIndex {
+    by_title: HashMap<String, Folder>,
+    // folder uid -> number of dashboards and subfolders inside
+    item_counts: HashMap<String, usize>,
+}
+
+impl FolderIndex {
+    pub fn get(&self, title: &str) -> Option<&Folder> {
+        self.by_title.get(title)
+    }
+
+    pub fn by_uid(&self, uid: &str) -> Option<&Folder> {
+        self.by_title.values().find(|f| f.uid == uid)
+    }
+
+    pub fn insert(&mut self, folder: Folder) {
+        self.by_title.insert(folder.title.clone(), folder);
+    }
+
+    /// Folders which held no items when the index was built and whose title isn't in `keep`.
+    pub fn empty_folders<'a>(
+        &'a self,
+        keep: &'a HashSet<String>,
+    ) -> impl Iterator<Item = &'a Folder> + 'a {
+        self.by_title
+            .values()
+            .filter(|f| !keep.contains(&f.title))
+            .filter(|f| self.item_counts.get(&f.uid).copied().unwrap_or(0) == 0)
+    }
+}
+
+/// Full paths of an instance's folders, e.g. `Team/Sub` for a nested folder.
+#[derive(Debug, Clone, Default)]
+pub struct FolderPaths {
+    // folder uid -> path
+    paths: HashMap<String, String>,
+}
+
+impl FolderPaths {
+    /// Builds the paths from folders as listed by the search, which names their parents.
+    fn new(folders: &[SimpleDashboard]) -> Self {
+        let by_uid = folders
+            .iter()
+            .map(|f| (f.uid.as_str(), f))
+            .collect::<HashMap<_, _>>();
+
+        let mut paths = HashMap::new();
+        for folder in folders {
+            let mut titles = vec![folder.title.as_str()];
+            let mut parent = folder.folder_uid.as_deref();
+            // the depth bound guards against cycles in a broken listing
+            while let Some(f) = parent.and_then(|uid| by_uid.get(uid)) {
+                if titles.len() > by_uid.len() {
+                    break;
+                }
+                titles.push(f.title.as_str());
+                parent = f.folder_uid.as_deref();
+            }
+            titles.reverse();
+            paths.insert(folder.uid.clone(), titles.join("/"));
+        }
+        Self { paths }
+    }
+
+    /// The path of the folder a dashboard lives in, `General` for the root.
+    pub fn path_of(&self, dashboard: &SimpleDashboard) -> String {
+        dashboard
+            .folder_uid
+            .as_deref()
+            .and_then(|uid| self.paths.get(uid).cloned())
+            .or_else(|| dashboard.folder_title.clone())
+            .unwrap_or_else(|| ROOT_FOLDER.to_owned())
+    }
+}
+
 impl GrafanaInstance {
     pub async fn get_all_folders(&self) -> Result<Vec<Folder>, GSError> {
         let endpoint = format!("{}/api/folders", &self.base_url());
-        let client = self.client();
 
-        let response = client
-            .get(endpoint)
-            .query(&[("limit", "1000"), ("page", "1")])
-            .send()
-            .await?
-            .error_for_status()?;
-        let text = response.text().await?;
+        self.get_all_pages(&endpoint, &[], FOLDER_PAGE_SIZE, |f: &Folder| &f.uid)
+            .await
+    }
 
-        Ok(serde_json::from_str::<Vec<Folder>>(&text)?)
+    /// Every dashboard and folder visible on the instance, as returned by the search.
+    pub async fn get_all_search_items(&self) -> Result<Vec<SimpleDashboard>, GSError> {
+        let endpoint = format!("{}/api/search", &self.base_url());
+
+        self.get_all_pages(
+            &endpoint,
+            &[("permission", "View"), ("sort", "alpha-asc")],
+            SEARCH_PAGE_SIZE,
+            |d: &SimpleDashboard| &d.uid,
+        )
+        .await
     }
 
-    // returns the folder uid on the instance
-    pub async fn ensure_folder(&self, title: &str) -> Result<Folder, GSError> {
-        debug!("Ensuring folder {} exists on {}...", title, self.base_url());
-        let matching_folder = self
-            .get_all_folders()
-            .await?
-            .into_iter()
-            .find(|f| f.title == title);
-        if let Some(folder) = matching_folder {
-            debug!("Folder {} already exists", title);
-            return Ok(folder);
+    /// The paths of all folders, nested ones included.
+    pub async fn get_folder_paths(&self) -> Result<FolderPaths, GSError> {
+        let endpoint = format!("{}/api/search", &self.base_url());
+
+        let folders = self
+            .get_all_pages(
+                &endpoint,
+                &[("type", "dash-folder"), ("sort", "alpha-asc")],
+                SEARCH_PAGE_SIZE,
+                |d: &SimpleDashboard| &d.uid,
+            )
+            .await?;
+        Ok(FolderPaths::new(&folders))
+    }
+
+    pub async fn get_folder_index(&self) -> Result<FolderIndex, GSError> {
+        let folders = self.get_all_folders().await?;
+        let items = self.get_all_search_items().await?;
+
+        let mut index = FolderIndex::default();
+        for item in items {
+            if let Some(folder_uid) = item.folder_uid {
+                *index.item_counts.entry(folder_uid).or_default() += 1;
+            }
+        }
+        for folder in folders {
+            index.insert(folder);
         }
 
+        debug!(
+            "{}: indexed {} folder(s)",
+            self.name(),
+            index.by_title.len()
+        );
+        Ok(index)
+    }
+
+    pub async fn create_folder(&self, title: &str) -> Result<Folder, GSError> {
         let endpoint = format!("{}/api/folders", &self.base_url());
         let client = self.client();
 
@@ -52,10 +167,10 @@ impl GrafanaInstance {
             uid: Uuid::new_v4().to_string(),
         };
 
-        let response = client
+        let request = client
             .post(endpoint)
-            .json(&folder_body)
-            .send()
+            .json(&folder_body);
+        let response = self.send(request)
             .await?
             .error_for_status()?;
         let text = response.text().await?;
@@ -72,19 +187,35 @@ impl GrafanaInstance {
 
         debug!("Deleting folder with uid: {}", uid);
 
-        client.delete(endpoint).send().await?.error_for_status()?;
+        self.send(client.delete(endpoint)).await?.error_for_status()?;
 
         Ok(())
     }
 
-    pub async fn remove_empty_folders(&self) -> Result<(), GSError> {
-        let all_folders = self.get_all_folders().await?;
-
-        for folder in all_folders {
-            if self.get_dashboards_in_folder(&folder.uid).await?.is_empty() {
+    /// Removes all folders that were empty according to `index`, except for those in `keep`.
+    ///
+    /// Deleting a folder deletes the dashboards inside, so each candidate is checked again
+    /// right before, in case a dashboard was saved into it since the index was built.
+    pub async fn remove_empty_folders(
+        &self,
+        index: &FolderIndex,
+        keep: &HashSet<String>,
+    ) -> Result<(), GSError> {
+        let mut jobs = index
+            .empty_folders(keep)
+            .map(|folder| async move {
+                let _slot = self.acquire_slot().await;
+                if !self.get_dashboards_in_folder(&folder.uid).await?.is_empty() {
+                    debug!("Keeping folder {}, it isn't empty anymore", folder.title);
+                    return Ok(());
+                }
                 info!("Deleting empty folder {}", folder.title);
-                self.remove_folder(&folder.uid).await?;
-            }
+                self.remove_folder(&folder.uid).await
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = jobs.next().await {
+            res?;
         }
 
         Ok(())
@@ -0,0 +1,85 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// UID of the dashboard which holds the leader lease on an instance.
+pub const LEASE_DASHBOARD_UID: &str = "grafana-sync-leader";
+
+/// Who leads the grafana-sync replicas, and until when.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lease {
+    pub holder: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct LeaseDashboardResponse {
+    dashboard: LeaseDashboard,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaseDashboard {
+    version: i64,
+    #[serde(default)]
+    grafana_sync_lease: Option<Lease>,
+}
+
+impl GrafanaInstance {
+    /// The lease stored in the lease dashboard, and the dashboard's version.
+    /// Returns `None` if the dashboard doesn't exist yet.
+    pub async fn get_lease(&self) -> Result<Option<(Option<Lease>, i64)>, GSError> {
+        let endpoint = format!(
+            "{}/api/dashboards/uid/{}",
+            &self.base_url(),
+            LEASE_DASHBOARD_UID
+        );
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response.error_for_status()?.text().await?;
+        let dashboard = serde_json::from_str::<LeaseDashboardResponse>(&text)?.dashboard;
+
+        Ok(Some((dashboard.grafana_sync_lease, dashboard.version)))
+    }
+
+    /// Stores `lease`, if the lease dashboard still is at `version` (or doesn't exist, for `None`).
+    /// Returns `false` if another replica changed it in the meantime.
+    pub async fn put_lease(&self, lease: &Lease, version: Option<i64>) -> Result<bool, GSError> {
+        let endpoint = format!("{}/api/dashboards/db", &self.base_url());
+        let client = self.client();
+
+        let mut dashboard = json!({
+            "uid": LEASE_DASHBOARD_UID,
+            "title": "grafana-sync leader lease",
+            "description": "Managed by grafana-sync, do not edit or sync.",
+            "panels": [],
+            "grafanaSyncLease": lease,
+        });
+        if let Some(version) = version {
+            dashboard["version"] = json!(version);
+        }
+
+        let request = client.post(endpoint).json(&json!({
+            "dashboard": dashboard,
+            "overwrite": false,
+            "message": format!("lease held by {}", lease.holder),
+        }));
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            debug!("Lease on {} was changed concurrently", self.name());
+            return Ok(false);
+        }
+        response.error_for_status()?;
+
+        Ok(true)
+    }
+}
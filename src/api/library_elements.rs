@@ -0,0 +1,98 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use chrono::{DateTime, Local};
+use log::{debug, info};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryElement {
+    pub uid: String,
+    pub name: String,
+    pub kind: i64,
+    #[serde(default)]
+    pub folder_uid: Option<String>,
+    pub model: serde_json::Value,
+    pub version: i64,
+    pub meta: LibraryElementMeta,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryElementMeta {
+    #[serde(default)]
+    pub folder_name: String,
+    pub updated: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct LibraryElementResponse {
+    result: LibraryElement,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LibraryElementBody<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<&'a str>,
+    folder_uid: Option<&'a str>,
+    name: &'a str,
+    model: &'a serde_json::Value,
+    kind: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<i64>,
+}
+
+impl GrafanaInstance {
+    /// Returns `None` if no library element with that uid exists.
+    pub async fn get_library_element(&self, uid: &str) -> Result<Option<LibraryElement>, GSError> {
+        let endpoint = format!("{}/api/library-elements/{}", &self.base_url(), uid);
+        let client = self.client();
+
+        debug!("Requesting library element of uid: {}", uid);
+
+        let response = self.send(client.get(endpoint)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response.error_for_status()?.text().await?;
+
+        Ok(Some(serde_json::from_str::<LibraryElementResponse>(&text)?.result))
+    }
+
+    /// Creates `element` with its uid, or updates the existing element at `current_version`.
+    pub async fn upsert_library_element(
+        &self,
+        element: &LibraryElement,
+        folder_uid: Option<&str>,
+        current_version: Option<i64>,
+    ) -> Result<(), GSError> {
+        let client = self.client();
+        let body = LibraryElementBody {
+            uid: current_version.is_none().then_some(element.uid.as_str()),
+            folder_uid,
+            name: &element.name,
+            model: &element.model,
+            kind: element.kind,
+            version: current_version,
+        };
+
+        let request = match current_version {
+            None => client.post(format!("{}/api/library-elements", &self.base_url())),
+            Some(_) => client.patch(format!(
+                "{}/api/library-elements/{}",
+                &self.base_url(),
+                element.uid
+            )),
+        };
+        self.send(request.json(&body)).await?.error_for_status()?;
+
+        info!(
+            "Replication of library panel \"{}\" to {} successful",
+            element.name,
+            self.name()
+        );
+        Ok(())
+    }
+}
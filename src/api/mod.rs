@@ -1,2 +1,13 @@
+pub mod alert_rules;
+pub mod annotations;
 pub mod dashboards;
-mod folders;
+pub mod datasources;
+pub mod folders;
+pub mod leases;
+pub mod library_elements;
+pub mod notifications;
+pub mod orgs;
+mod paging;
+pub mod playlists;
+pub mod plugins;
+pub mod preferences;
@@ -0,0 +1,189 @@
+use crate::api::alert_rules::PATH_SEGMENT;
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::info;
+use percent_encoding::utf8_percent_encode;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Placeholder the provisioning API returns instead of secure contact point settings.
+/// Sent back unchanged, it makes Grafana keep the value it already has.
+pub const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactPoint {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uid: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+    #[serde(default)]
+    pub disable_resolve_message: bool,
+}
+
+impl ContactPoint {
+    /// Names of the settings the instance didn't reveal.
+    pub fn redacted_settings(&self) -> Vec<&str> {
+        self.settings
+            .iter()
+            .filter(|(_, v)| v.as_str() == Some(REDACTED))
+            .map(|(k, _)| k.as_str())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotificationTemplate {
+    pub name: String,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MuteTiming {
+    pub name: String,
+    #[serde(default)]
+    pub time_intervals: Vec<Value>,
+}
+
+impl GrafanaInstance {
+    async fn get_provisioned<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<T, GSError> {
+        let endpoint = format!("{}/api/v1/provisioning/{}", &self.base_url(), path);
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<T>(&text)?)
+    }
+
+    pub async fn get_contact_points(&self) -> Result<Vec<ContactPoint>, GSError> {
+        self.get_provisioned("contact-points").await
+    }
+
+    pub async fn get_notification_templates(&self) -> Result<Vec<NotificationTemplate>, GSError> {
+        // Grafana answers with null rather than an empty list
+        Ok(self
+            .get_provisioned::<Option<Vec<NotificationTemplate>>>("templates")
+            .await?
+            .unwrap_or_default())
+    }
+
+    pub async fn get_mute_timings(&self) -> Result<Vec<MuteTiming>, GSError> {
+        Ok(self
+            .get_provisioned::<Option<Vec<MuteTiming>>>("mute-timings")
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// The whole notification policy tree, kept as raw JSON.
+    pub async fn get_notification_policies(&self) -> Result<Value, GSError> {
+        self.get_provisioned("policies").await
+    }
+
+    /// Creates the contact point, or updates the one with the uid given.
+    /// Settings which are still redacted keep their value on the instance.
+    pub async fn upsert_contact_point(
+        &self,
+        contact_point: &ContactPoint,
+        uid: Option<&str>,
+    ) -> Result<(), GSError> {
+        let client = self.client();
+        let endpoint = format!("{}/api/v1/provisioning/contact-points", &self.base_url());
+
+        let request = match uid {
+            None => client.post(endpoint),
+            Some(uid) => client.put(format!("{endpoint}/{uid}")),
+        };
+        let request = request
+            .header("X-Disable-Provenance", "true")
+            .json(contact_point);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of contact point \"{}\" to {} successful",
+            contact_point.name,
+            self.name()
+        );
+        Ok(())
+    }
+
+    pub async fn put_notification_template(
+        &self,
+        template: &NotificationTemplate,
+    ) -> Result<(), GSError> {
+        let endpoint = format!(
+            "{}/api/v1/provisioning/templates/{}",
+            &self.base_url(),
+            utf8_percent_encode(&template.name, PATH_SEGMENT)
+        );
+        let client = self.client();
+
+        let request = client
+            .put(endpoint)
+            .header("X-Disable-Provenance", "true")
+            .json(&serde_json::json!({ "template": template.template }));
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of notification template \"{}\" to {} successful",
+            template.name,
+            self.name()
+        );
+        Ok(())
+    }
+
+    pub async fn upsert_mute_timing(&self, timing: &MuteTiming, exists: bool) -> Result<(), GSError> {
+        let client = self.client();
+        let endpoint = format!("{}/api/v1/provisioning/mute-timings", &self.base_url());
+
+        let request = match exists {
+            false => client.post(endpoint),
+            true => client.put(format!(
+                "{endpoint}/{}",
+                utf8_percent_encode(&timing.name, PATH_SEGMENT)
+            )),
+        };
+        let request = request
+            .header("X-Disable-Provenance", "true")
+            .json(timing);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of mute timing \"{}\" to {} successful",
+            timing.name,
+            self.name()
+        );
+        Ok(())
+    }
+
+    pub async fn put_notification_policies(&self, tree: &Value) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/v1/provisioning/policies", &self.base_url());
+        let client = self.client();
+
+        let request = client
+            .put(endpoint)
+            .header("X-Disable-Provenance", "true")
+            .json(tree);
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!(
+            "Replication of notification policies to {} successful",
+            self.name()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Org {
+    pub id: u64,
+    pub name: String,
+}
+
+impl GrafanaInstance {
+    /// All orgs of the server. Needs a Grafana server admin.
+    pub async fn get_orgs(&self) -> Result<Vec<Org>, GSError> {
+        let endpoint = format!("{}/api/orgs", &self.base_url());
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<Org>>(&text)?)
+    }
+}
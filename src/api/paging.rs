@@ -0,0 +1,62 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::debug;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+
+// Safety net against endpoints which ignore the `page` parameter
+const MAX_PAGES: usize = 1000;
+
+impl GrafanaInstance {
+    /// Requests an endpoint supporting `limit`/`page` until a page comes back
+    /// which is not full, and concatenates all pages.
+    ///
+    /// Fails with [`GSError::TruncatedListing`] if a page repeats items of an earlier one,
+    /// which happens on Grafana versions that don't support paging this endpoint.
+    pub(crate) async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        page_size: usize,
+        key: impl Fn(&T) -> &str,
+    ) -> Result<Vec<T>, GSError> {
+        let client = self.client();
+        let limit = page_size.to_string();
+        let mut items = Vec::new();
+        let mut seen = HashSet::new();
+
+        for page in 1..=MAX_PAGES {
+            let page_str = page.to_string();
+            let request = client
+                .get(endpoint)
+                .query(query)
+                .query(&[("limit", limit.as_str()), ("page", page_str.as_str())]);
+            let response = self.send(request)
+                .await?
+                .error_for_status()?;
+            let text = response.text().await?;
+
+            let batch = serde_json::from_str::<Vec<T>>(&text)?;
+            let received = batch.len();
+            for item in batch {
+                if !seen.insert(key(&item).to_owned()) {
+                    return Err(GSError::TruncatedListing(
+                        endpoint.to_owned(),
+                        format!("page {page} repeated item '{}'", key(&item)),
+                    ));
+                }
+                items.push(item);
+            }
+
+            debug!("{endpoint}: page {page} returned {received} item(s)");
+            if received < page_size {
+                return Ok(items);
+            }
+        }
+
+        Err(GSError::TruncatedListing(
+            endpoint.to_owned(),
+            format!("gave up after {MAX_PAGES} pages"),
+        ))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlaylistSummary {
+    pub uid: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    // dashboard_by_uid, dashboard_by_tag or the instance specific dashboard_by_id
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub uid: String,
+    pub name: String,
+    pub interval: String,
+    #[serde(default)]
+    pub items: Vec<PlaylistItem>,
+}
+
+impl GrafanaInstance {
+    pub async fn get_playlists(&self) -> Result<Vec<PlaylistSummary>, GSError> {
+        let endpoint = format!("{}/api/playlists", &self.base_url());
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<PlaylistSummary>>(&text)?)
+    }
+
+    pub async fn get_playlist(&self, uid: &str) -> Result<Playlist, GSError> {
+        let endpoint = format!("{}/api/playlists/{}", &self.base_url(), uid);
+        let client = self.client();
+
+        debug!("Requesting playlist of uid: {}", uid);
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Playlist>(&text)?)
+    }
+
+    /// Creates the playlist with its uid, or replaces the existing one.
+    pub async fn upsert_playlist(&self, playlist: &Playlist, exists: bool) -> Result<(), GSError> {
+        let client = self.client();
+        let endpoint = format!("{}/api/playlists", &self.base_url());
+
+        let request = match exists {
+            false => client.post(endpoint),
+            true => client.put(format!("{endpoint}/{}", playlist.uid)),
+        };
+        self.send(request.json(playlist)).await?.error_for_status()?;
+
+        info!(
+            "Replication of playlist \"{}\" to {} successful",
+            playlist.name,
+            self.name()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Plugin {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+impl GrafanaInstance {
+    /// Lists the installed plugins of one type, core plugins included.
+    pub async fn get_plugins(&self, type_name: &str) -> Result<Vec<Plugin>, GSError> {
+        let endpoint = format!("{}/api/plugins", &self.base_url());
+        let client = self.client();
+
+        let request = client
+            .get(endpoint)
+            .query(&[("type", type_name), ("enabled", "1")]);
+        let response = self.send(request)
+            .await?
+            .error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<Vec<Plugin>>(&text)?)
+    }
+}
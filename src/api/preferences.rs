@@ -0,0 +1,39 @@
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::info;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgPreferences {
+    #[serde(default, rename = "homeDashboardUID")]
+    pub home_dashboard_uid: Option<String>,
+}
+
+impl GrafanaInstance {
+    pub async fn get_org_preferences(&self) -> Result<OrgPreferences, GSError> {
+        let endpoint = format!("{}/api/org/preferences", &self.base_url());
+        let client = self.client();
+
+        let response = self.send(client.get(endpoint)).await?.error_for_status()?;
+        let text = response.text().await?;
+
+        Ok(serde_json::from_str::<OrgPreferences>(&text)?)
+    }
+
+    /// Only changes the home dashboard, all other preferences stay as they are.
+    pub async fn set_home_dashboard(&self, uid: &str) -> Result<(), GSError> {
+        let endpoint = format!("{}/api/org/preferences", &self.base_url());
+        let client = self.client();
+
+        let request = client
+            .patch(endpoint)
+            .json(&serde_json::json!({ "homeDashboardUID": uid }));
+        self.send(request)
+            .await?
+            .error_for_status()?;
+
+        info!("Set home dashboard of {} to '{uid}'", self.name());
+        Ok(())
+    }
+}
@@ -1,18 +1,173 @@
 use crate::error::GSError;
-use crate::instance::GrafanaInstance;
+use crate::dashboard_cache::STATE_FILE_NAME;
+use crate::datasource_map::DatasourceMapEntry;
+use crate::instance::{
+    Auth, ClientIdentity, GrafanaInstance, InstanceOptions, OrgSelection, ProxyOptions, TlsOptions,
+    DEFAULT_MAX_CONCURRENCY,
+};
+use crate::selector::{
+    AlertRuleSelector, NotificationSelector, RuleConfig, Selector, SyncGroup, SyncGroupConfig,
+};
+use crate::leader::{LeaderElectionSettings, LeaseBackend};
+use crate::oauth::OAuth2Config;
+use crate::trigger::{TriggerSettings, DEFAULT_DEBOUNCE, MIN_SECRET_LEN};
+use crate::schedule::{
+    parse_duration, Blackout, MissedTicks, Schedule, SyncWindow, Trigger, MAX_DURATION,
+};
+use crate::shutdown::DEFAULT_SHUTDOWN_TIMEOUT;
+use crate::transform::{Pipeline, TransformConfig};
+use chrono::{NaiveTime, Weekday};
 use log::{debug, info, warn};
+use serde::Deserialize;
 use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{fs, io};
 use tracing::instrument;
 
+/// What happens when a synced dashboard stops being selected on one instance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UntagPolicy {
+    /// Stop syncing the UID everywhere, but leave all copies as they are.
+    #[default]
+    Detach,
+    /// Stop syncing the UID and remove the selecting tags from all other copies as well.
+    Propagate,
+}
+
+/// What happens when a dashboard depends on something its target instance doesn't have,
+/// like a datasource or a panel plugin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingDependencyPolicy {
+    /// Don't check at all.
+    #[default]
+    Ignore,
+    /// Import anyway, but log a warning.
+    Warn,
+    /// Log a warning and don't import the dashboard onto that instance.
+    Skip,
+    /// Abort the cycle.
+    Fail,
+}
+
+/// The `auth` section of an instance. Secrets are encrypted right after reading.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum AuthConfig {
+    Bearer { token: String },
+    Basic { username: String, password: String },
+    #[serde(rename = "oauth2")]
+    OAuth2 {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        #[serde(default)]
+        scopes: Vec<String>,
+        #[serde(default)]
+        audience: Option<String>,
+    },
+    None,
+}
+
+/// The `tls` section of an instance.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsConfig {
+    ca_files: Vec<PathBuf>,
+    min_version: Option<String>,
+    insecure_skip_verify: bool,
+}
+
+/// The `proxy` section of an instance.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProxyConfig {
+    url: String,
+    #[serde(default)]
+    no_proxy: Vec<String>,
+}
+
+/// The `schedule` section.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScheduleConfig {
+    every: Option<String>,
+    cron: Option<String>,
+    jitter: Option<String>,
+    missed_ticks: MissedTicks,
+    windows: Vec<SyncWindowConfig>,
+    blackouts: Vec<Blackout>,
+}
+
+/// A recurring window of the `schedule` section, times as `HH:MM`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncWindowConfig {
+    #[serde(default)]
+    days: Vec<String>,
+    from: String,
+    to: String,
+}
+
+/// The `trigger` section.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggerConfig {
+    listen: String,
+    secret: String,
+    #[serde(default)]
+    debounce: Option<String>,
+}
+
+/// Which wall display settings are synced, and from where.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplaySettings {
+    // base url of the instance whose settings are copied, the first instance if unset
+    pub source: Option<String>,
+    pub playlists: bool,
+    pub home_dashboard: bool,
+}
+
+/// How annotations of synced dashboards are synced.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnnotationSettings {
+    // how far back annotations are synced
+    pub window_days: u64,
+}
+
+impl Default for AnnotationSettings {
+    fn default() -> Self {
+        Self { window_days: 7 }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub instances: Vec<GrafanaInstance>,
-    pub sync_tag: String,
-    pub sync_rate_mins: u64,
+    pub sync_tag: Option<String>,
+    pub selector: Selector,
+    pub schedule: Schedule,
+    pub cache_file: Option<PathBuf>,
+    // keeps detached dashboards and known annotations if there is no cache file
+    pub state_file: PathBuf,
+    // how long a running cycle may take to finish once shutdown was requested
+    pub shutdown_timeout: Duration,
+    pub untag_policy: UntagPolicy,
+    pub missing_datasources: MissingDependencyPolicy,
+    pub missing_panel_plugins: MissingDependencyPolicy,
+    pub alert_rules: Option<AlertRuleSelector>,
+    pub notifications: Option<NotificationSelector>,
+    pub displays: Option<DisplaySettings>,
+    pub annotations: Option<AnnotationSettings>,
+    pub leader_election: Option<LeaderElectionSettings>,
+    pub trigger: Option<TriggerSettings>,
 }
 
 impl Config {
@@ -64,7 +219,37 @@ impl Config {
     }
 
     #[instrument]
-    fn collect_instances(config: &Value) -> Result<Vec<GrafanaInstance>, GSError> {
+    fn read_optional_string_from_config(
+        config: &Value,
+        key: &str,
+    ) -> Result<Option<String>, GSError> {
+        match Self::get_yaml_path(config, key) {
+            Err(_) => Ok(None),
+            Ok(value) if value.is_null() => Ok(None),
+            Ok(value) => value
+                .as_str()
+                .map(|s| Some(s.to_string()))
+                .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.to_string(), "String")),
+        }
+    }
+
+    #[instrument]
+    fn read_optional_u64_from_config(config: &Value, key: &str) -> Result<Option<u64>, GSError> {
+        match Self::get_yaml_path(config, key) {
+            Err(_) => Ok(None),
+            Ok(value) if value.is_null() => Ok(None),
+            Ok(value) => value
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.to_string(), "u64")),
+        }
+    }
+
+    #[instrument]
+    fn collect_instances(
+        config: &Value,
+        default_max_concurrency: usize,
+    ) -> Result<Vec<GrafanaInstance>, GSError> {
         let mut instances = Vec::new();
 
         let cfg_instances = Self::get_yaml_path(config, "instances");
@@ -93,55 +278,711 @@ impl Config {
                 .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "String"))?
                 .to_string();
 
-            let key = format!("instances[{}].api_token", i);
-            let api_token = instance
-                .get("api_token")
-                .ok_or_else(|| GSError::ConfigKeyMissing(key.clone()))?
-                .as_str()
-                .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "String"))?
-                .to_string()
-                .into();
+            let auth = Self::read_instance_auth(instance, i)?;
+
+            let key = format!("instances[{}].headers", i);
+            let headers = match instance.get("headers") {
+                None => vec![],
+                Some(value) if value.is_null() => vec![],
+                Some(value) if value.is_mapping() => {
+                    serde_yaml::from_value::<BTreeMap<String, String>>(value.clone())?
+                        .into_iter()
+                        .map(|(name, value)| (name, value.into()))
+                        .collect()
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Mapping")),
+            };
+
+            let client_identity = match (
+                Self::read_instance_path(instance, i, "client_cert")?,
+                Self::read_instance_path(instance, i, "client_key")?,
+            ) {
+                (None, None) => None,
+                (Some(cert), Some(key)) => Some(ClientIdentity {
+                    cert: fs::read_to_string(cert)?.into(),
+                    key: fs::read_to_string(key)?.into(),
+                }),
+                (Some(_), None) => {
+                    return Err(GSError::ConfigKeyMissing(format!(
+                        "instances[{}].client_key",
+                        i
+                    )))
+                }
+                (None, Some(_)) => {
+                    return Err(GSError::ConfigKeyMissing(format!(
+                        "instances[{}].client_cert",
+                        i
+                    )))
+                }
+            };
+
+            let key = format!("instances[{}].max_concurrency", i);
+            let max_concurrency = match instance.get("max_concurrency") {
+                None => default_max_concurrency,
+                Some(value) => value
+                    .as_u64()
+                    .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "u64"))?
+                    as usize,
+            };
+
+            let key = format!("instances[{}].partial_view", i);
+            let partial_view = match instance.get("partial_view") {
+                None => false,
+                Some(value) => value
+                    .as_bool()
+                    .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "bool"))?,
+            };
+
+            let tls = Self::read_instance_tls(instance, i)?;
 
-            instances.push(GrafanaInstance::new(url, api_token)?);
+            let key = format!("instances[{}].proxy", i);
+            let proxy = match instance.get("proxy") {
+                None => None,
+                Some(value) if value.is_null() => None,
+                Some(value) if value.is_mapping() => {
+                    let proxy = serde_yaml::from_value::<ProxyConfig>(value.clone())?;
+                    Some(ProxyOptions {
+                        url: proxy.url.into(),
+                        no_proxy: proxy.no_proxy,
+                    })
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Mapping")),
+            };
+
+            let key = format!("instances[{}].org_id", i);
+            let org = match instance.get("org_id") {
+                None => OrgSelection::Current,
+                Some(value) if value.is_null() => OrgSelection::Current,
+                Some(value) if value.as_str() == Some("all") => OrgSelection::All,
+                Some(value) => value
+                    .as_u64()
+                    .map(OrgSelection::Id)
+                    .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "u64 | \"all\""))?,
+            };
+
+            let key = format!("instances[{}].org_names", i);
+            let org_names = match instance.get("org_names") {
+                None => HashMap::new(),
+                Some(value) if value.is_null() => HashMap::new(),
+                Some(value) if value.is_mapping() => {
+                    serde_yaml::from_value::<HashMap<String, String>>(value.clone())?
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Mapping")),
+            };
+
+            let key = format!("instances[{}].datasources", i);
+            let datasources = match instance.get("datasources") {
+                None => vec![],
+                Some(value) if value.is_null() => vec![],
+                Some(value) if value.is_sequence() => {
+                    serde_yaml::from_value::<Vec<DatasourceMapEntry>>(value.clone())?
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Sequence")),
+            };
+
+            let key = format!("instances[{}].transforms", i);
+            let transforms = match instance.get("transforms") {
+                None => Pipeline::default(),
+                Some(value) if value.is_null() => Pipeline::default(),
+                Some(value) if value.is_sequence() => Pipeline::compile(
+                    &serde_yaml::from_value::<Vec<TransformConfig>>(value.clone())?,
+                )?,
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Sequence")),
+            };
+
+            let key = format!("instances[{}].contact_point_secrets", i);
+            let contact_point_secrets = match instance.get("contact_point_secrets") {
+                None => HashMap::new(),
+                Some(value) if value.is_null() => HashMap::new(),
+                Some(value) if value.is_mapping() => {
+                    serde_yaml::from_value::<HashMap<String, HashMap<String, String>>>(
+                        value.clone(),
+                    )?
+                    .into_iter()
+                    .map(|(name, settings)| {
+                        let settings = settings
+                            .into_iter()
+                            .map(|(setting, secret)| (setting, secret.into()))
+                            .collect();
+                        (name, settings)
+                    })
+                    .collect()
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Mapping")),
+            };
+
+            let key = format!("instances[{}].blackouts", i);
+            let blackouts = match instance.get("blackouts") {
+                None => vec![],
+                Some(value) if value.is_null() => vec![],
+                Some(value) if value.is_sequence() => {
+                    serde_yaml::from_value::<Vec<Blackout>>(value.clone())?
+                }
+                Some(_) => return Err(GSError::ConfigKeyTypeWrong(key, "Sequence")),
+            };
+
+            let options = InstanceOptions {
+                max_concurrency,
+                headers,
+                client_identity,
+                tls,
+                proxy,
+                org,
+                org_names,
+                datasources,
+                transforms,
+                contact_point_secrets,
+                blackouts,
+                partial_view,
+            };
+
+            instances.push(GrafanaInstance::new(url, auth, options)?);
         }
 
         info!("Loaded {} instance(s):", json_instances.len());
         for instance in &instances {
-            info!("  - {}", instance.base_url());
+            info!("  - {}", instance.name());
         }
 
         Ok(instances)
     }
 
+    /// Reads `api_token`, the shorthand for bearer auth, or the `auth` section of an instance.
+    fn read_instance_auth(instance: &Value, i: usize) -> Result<Auth, GSError> {
+        let key = format!("instances[{}].api_token", i);
+        if let Some(token) = instance.get("api_token") {
+            let token = token
+                .as_str()
+                .ok_or_else(|| GSError::ConfigKeyTypeWrong(key.clone(), "String"))?;
+            return Ok(Auth::Bearer(token.to_string().into()));
+        }
+
+        let Some(auth) = instance.get("auth") else {
+            return Err(GSError::ConfigKeyMissing(key));
+        };
+        Ok(match serde_yaml::from_value::<AuthConfig>(auth.clone())? {
+            AuthConfig::Bearer { token } => Auth::Bearer(token.into()),
+            AuthConfig::Basic { username, password } => Auth::Basic {
+                username,
+                password: password.into(),
+            },
+            AuthConfig::OAuth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scopes,
+                audience,
+            } => Auth::OAuth2(OAuth2Config {
+                token_url,
+                client_id,
+                client_secret: client_secret.into(),
+                scopes,
+                audience,
+            }),
+            AuthConfig::None => Auth::None,
+        })
+    }
+
+    fn read_instance_tls(instance: &Value, i: usize) -> Result<TlsOptions, GSError> {
+        let config = match instance.get("tls") {
+            None => TlsConfig::default(),
+            Some(value) if value.is_null() => TlsConfig::default(),
+            Some(value) => serde_yaml::from_value::<TlsConfig>(value.clone())?,
+        };
+
+        let min_version = match config.min_version.as_deref() {
+            None => None,
+            Some("1.0") => Some(reqwest::tls::Version::TLS_1_0),
+            Some("1.1") => Some(reqwest::tls::Version::TLS_1_1),
+            Some("1.2") => Some(reqwest::tls::Version::TLS_1_2),
+            Some("1.3") => Some(reqwest::tls::Version::TLS_1_3),
+            Some(_) => {
+                return Err(GSError::ConfigKeyTypeWrong(
+                    format!("instances[{}].tls.min_version", i),
+                    "\"1.0\" | \"1.1\" | \"1.2\" | \"1.3\"",
+                ))
+            }
+        };
+
+        Ok(TlsOptions {
+            ca_bundles: config
+                .ca_files
+                .iter()
+                .map(fs::read)
+                .collect::<Result<_, _>>()?,
+            min_version,
+            insecure_skip_verify: config.insecure_skip_verify,
+        })
+    }
+
+    fn read_instance_path(
+        instance: &Value,
+        i: usize,
+        name: &str,
+    ) -> Result<Option<PathBuf>, GSError> {
+        match instance.get(name) {
+            None => Ok(None),
+            Some(value) if value.is_null() => Ok(None),
+            Some(value) => value.as_str().map(|p| Some(PathBuf::from(p))).ok_or_else(|| {
+                GSError::ConfigKeyTypeWrong(format!("instances[{}].{}", i, name), "String")
+            }),
+        }
+    }
+
+    /// The `schedule` section, falling back to `sync_rate_mins` for when cycles are due.
+    #[instrument]
+    fn read_schedule(config: &Value) -> Result<Schedule, GSError> {
+        let sync_rate_mins = Self::read_optional_u64_from_config(config, "sync_rate_mins")?;
+        let section = match config.get("schedule") {
+            None => ScheduleConfig::default(),
+            Some(value) if value.is_null() => ScheduleConfig::default(),
+            Some(value) => serde_yaml::from_value::<ScheduleConfig>(value.clone())?,
+        };
+
+        let duration = |key: &str, text: &str| {
+            parse_duration(text).ok_or_else(|| {
+                GSError::ConfigKeyTypeWrong(format!("schedule.{key}"), "duration like 90s or 1h30m")
+            })
+        };
+
+        let trigger = match (&section.every, &section.cron, sync_rate_mins) {
+            (Some(_), Some(_), _) => {
+                return Err(GSError::InvalidSchedule(
+                    "`every` and `cron` can't be combined".to_owned(),
+                ))
+            }
+            (Some(every), None, _) => Trigger::Every(duration("every", every)?),
+            (None, Some(cron), _) => Trigger::cron(cron)?,
+            (None, None, Some(mins)) => Trigger::Every(
+                mins.checked_mul(60)
+                    .map(Duration::from_secs)
+                    .filter(|d| *d <= MAX_DURATION)
+                    .ok_or_else(|| {
+                        GSError::InvalidSchedule("sync_rate_mins is too large".to_owned())
+                    })?,
+            ),
+            (None, None, None) => {
+                return Err(GSError::ConfigKeyMissing("sync_rate_mins".to_string()))
+            }
+        };
+        if trigger == Trigger::Every(Duration::ZERO) {
+            return Err(GSError::InvalidSchedule("the interval must be at least 1s".to_owned()));
+        }
+
+        let mut windows = Vec::new();
+        for (i, window) in section.windows.iter().enumerate() {
+            let time = |key: &str, text: &str| {
+                NaiveTime::parse_from_str(text, "%H:%M").map_err(|_| {
+                    GSError::ConfigKeyTypeWrong(format!("schedule.windows[{i}].{key}"), "HH:MM")
+                })
+            };
+            let days = window
+                .days
+                .iter()
+                .map(|day| {
+                    Weekday::from_str(day).map_err(|_| {
+                        let key = format!("schedule.windows[{i}].days");
+                        GSError::ConfigKeyTypeWrong(key, "mon, tue, ..., sun")
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            windows.push(SyncWindow {
+                days,
+                from: time("from", &window.from)?,
+                to: time("to", &window.to)?,
+            });
+        }
+
+        Ok(Schedule {
+            trigger,
+            jitter: match &section.jitter {
+                Some(jitter) => duration("jitter", jitter)?,
+                None => Duration::ZERO,
+            },
+            missed_ticks: section.missed_ticks,
+            windows,
+            blackouts: section.blackouts,
+        })
+    }
+
+    #[instrument]
+    fn read_missing_policy(config: &Value, key: &str) -> Result<MissingDependencyPolicy, GSError> {
+        match Self::read_optional_string_from_config(config, key)?.as_deref() {
+            None | Some("ignore") => Ok(MissingDependencyPolicy::Ignore),
+            Some("warn") => Ok(MissingDependencyPolicy::Warn),
+            Some("skip") => Ok(MissingDependencyPolicy::Skip),
+            Some("fail") => Ok(MissingDependencyPolicy::Fail),
+            Some(_) => Err(GSError::ConfigKeyTypeWrong(
+                key.to_string(),
+                "\"ignore\" | \"warn\" | \"skip\" | \"fail\"",
+            )),
+        }
+    }
+
+    #[instrument]
+    fn collect_sync_groups(config: &Value, sync_tag: Option<&str>) -> Result<Selector, GSError> {
+        let mut groups = Vec::new();
+
+        if let Some(tag) = sync_tag {
+            groups.push(SyncGroup::from_tag(tag));
+        }
+
+        if let Ok(value) = Self::get_yaml_path(config, "sync_groups") {
+            if !value.is_null() {
+                if !value.is_sequence() {
+                    return Err(GSError::ConfigKeyTypeWrong(
+                        "sync_groups".to_string(),
+                        "Sequence",
+                    ));
+                }
+                let group_configs = serde_yaml::from_value::<Vec<SyncGroupConfig>>(value.clone())?;
+                for group in &group_configs {
+                    groups.push(SyncGroup::compile(group)?);
+                }
+            }
+        }
+
+        if groups.is_empty() {
+            return Err(GSError::ConfigKeyMissing("sync_tag".to_string()));
+        }
+
+        let exclude = match Self::get_yaml_path(config, "sync_exclude") {
+            Ok(value) if !value.is_null() => {
+                serde_yaml::from_value::<Vec<RuleConfig>>(value.clone())?
+            }
+            _ => vec![],
+        };
+
+        Selector::new(groups, &exclude)
+    }
+
+    fn check_source(
+        instances: &[GrafanaInstance],
+        section: &str,
+        source: Option<&str>,
+    ) -> Result<(), GSError> {
+        match source {
+            Some(source) if !instances.iter().any(|i| i.name() == source) => {
+                Err(GSError::InvalidSetting(
+                    section.to_owned(),
+                    format!("the source {source} is no configured instance"),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn use_config_file<P: AsRef<Path>>(path: P) -> Result<Config, GSError> {
         let file = Self::get_or_create(&path)?;
 
         let config = serde_yaml::from_reader::<_, Value>(file)?;
 
-        let sync_tag = Self::read_string_from_config(&config, "sync_tag")?;
-        let sync_rate_mins = Self::read_u64_from_config(&config, "sync_rate_mins")?;
+        let sync_tag = Self::read_optional_string_from_config(&config, "sync_tag")?;
+        let selector = Self::collect_sync_groups(&config, sync_tag.as_deref())?;
+        let schedule = Self::read_schedule(&config)?;
+
+        let max_concurrency = Self::read_optional_u64_from_config(&config, "max_concurrency")?
+            .map(|n| n as usize)
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY);
+
+        let cache_file =
+            Self::read_optional_string_from_config(&config, "cache_file")?.map(PathBuf::from);
+        let state_file = path.as_ref().with_file_name(STATE_FILE_NAME);
+
+        let shutdown_timeout =
+            Self::read_optional_u64_from_config(&config, "shutdown_timeout_secs")?
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+
+        let untag_policy =
+            match Self::read_optional_string_from_config(&config, "untag_policy")?.as_deref() {
+                None | Some("detach") => UntagPolicy::Detach,
+                Some("propagate") => UntagPolicy::Propagate,
+                Some(_) => {
+                    return Err(GSError::ConfigKeyTypeWrong(
+                        "untag_policy".to_string(),
+                        "\"detach\" | \"propagate\"",
+                    ))
+                }
+            };
+
+        let missing_datasources = Self::read_missing_policy(&config, "missing_datasources")?;
+        let missing_panel_plugins = Self::read_missing_policy(&config, "missing_panel_plugins")?;
+
+        let alert_rules = match Self::get_yaml_path(&config, "alert_rules") {
+            Err(_) => None,
+            Ok(value) if value.is_null() => None,
+            Ok(value) => Some(serde_yaml::from_value::<AlertRuleSelector>(value.clone())?),
+        };
+
+        let annotations = match Self::get_yaml_path(&config, "annotations") {
+            Err(_) => None,
+            Ok(value) if value.is_null() => None,
+            Ok(value) => Some(serde_yaml::from_value::<AnnotationSettings>(value.clone())?),
+        };
+
+        let instances = Self::collect_instances(&config, max_concurrency)?;
+
+        let notifications = match Self::get_yaml_path(&config, "notifications") {
+            Err(_) => None,
+            Ok(value) if value.is_null() => None,
+            Ok(value) => {
+                let selector = serde_yaml::from_value::<NotificationSelector>(value.clone())?;
+                selector.validate()?;
+                Self::check_source(&instances, "notifications", selector.source.as_deref())?;
+                Some(selector)
+            }
+        };
+
+        let displays = match Self::get_yaml_path(&config, "displays") {
+            Err(_) => None,
+            Ok(value) if value.is_null() => None,
+            Ok(value) => {
+                let displays = serde_yaml::from_value::<DisplaySettings>(value.clone())?;
+                Self::check_source(&instances, "displays", displays.source.as_deref())?;
+                Some(displays)
+            }
+        };
+
+        let leader_election = match Self::get_yaml_path(&config, "leader_election") {
+            Err(_) => None,
+            Ok(value) if value.is_null() => None,
+            Ok(value) => {
+                let settings = serde_yaml::from_value::<LeaderElectionSettings>(value.clone())?;
+                if let LeaseBackend::Grafana { instance } = &settings.backend {
+                    if instances.is_empty() {
+                        return Err(GSError::InvalidSetting(
+                            "leader_election".to_owned(),
+                            "the grafana backend needs an instance".to_owned(),
+                        ));
+                    }
+                    Self::check_source(&instances, "leader_election", instance.as_deref())?;
+                }
+                Some(settings)
+            }
+        };
 
-        let instances = Self::collect_instances(&config)?;
+        let trigger = match config.get("trigger") {
+            None => None,
+            Some(value) if value.is_null() => None,
+            Some(value) => {
+                let section = serde_yaml::from_value::<TriggerConfig>(value.clone())?;
+                let listen = section.listen.parse().map_err(|_| {
+                    GSError::ConfigKeyTypeWrong("trigger.listen".to_string(), "address:port")
+                })?;
+                let debounce = match section.debounce.as_deref() {
+                    None => DEFAULT_DEBOUNCE,
+                    Some(text) => parse_duration(text).ok_or_else(|| {
+                        GSError::ConfigKeyTypeWrong(
+                            "trigger.debounce".to_string(),
+                            "duration like 5s or 1m",
+                        )
+                    })?,
+                };
+                if section.secret.chars().count() < MIN_SECRET_LEN {
+                    return Err(GSError::InvalidSetting(
+                        "trigger".to_string(),
+                        format!("the secret needs at least {MIN_SECRET_LEN} characters"),
+                    ));
+                }
+                Some(TriggerSettings {
+                    listen,
+                    secret: section.secret.into(),
+                    debounce,
+                })
+            }
+        };
 
         Ok(Config {
             sync_tag,
+            selector,
             instances,
-            sync_rate_mins,
+            schedule,
+            cache_file,
+            state_file,
+            shutdown_timeout,
+            untag_policy,
+            missing_datasources,
+            missing_panel_plugins,
+            alert_rules,
+            notifications,
+            displays,
+            annotations,
+            leader_election,
+            trigger,
         })
     }
 
     pub(crate) fn dbg_print(&self) {
         debug!("Full configuration:");
 
-        debug!("  + Sync Tag: {}", self.sync_tag);
-        debug!("  + Sync Rate: {}", self.sync_rate_mins);
+        if let Some(tag) = &self.sync_tag {
+            debug!("  + Sync Tag: {}", tag);
+        }
+        for group in self.selector.groups() {
+            debug!("  + Sync Group: {}", group.name());
+        }
+        debug!("  + Schedule: {:?}", self.schedule);
+        debug!("  + Shutdown Timeout: {:?}", self.shutdown_timeout);
+        debug!("  + Untag Policy: {:?}", self.untag_policy);
+        debug!("  + Missing Datasources: {:?}", self.missing_datasources);
+        debug!("  + Missing Panel Plugins: {:?}", self.missing_panel_plugins);
+        if let Some(selector) = &self.alert_rules {
+            debug!("  + Alert Rules: {:?}", selector);
+        }
+        if let Some(selector) = &self.notifications {
+            debug!("  + Notifications: {:?}", selector);
+        }
+        if let Some(displays) = &self.displays {
+            debug!("  + Displays: {:?}", displays);
+        }
+        if let Some(annotations) = &self.annotations {
+            debug!("  + Annotations: {:?}", annotations);
+        }
+        if let Some(election) = &self.leader_election {
+            debug!("  + Leader Election: {:?}", election);
+        }
+        if let Some(trigger) = &self.trigger {
+            debug!("  + Trigger: {} (debounce {:?})", trigger.listen, trigger.debounce);
+        }
+        if let Some(path) = &self.cache_file {
+            debug!("  + Cache File: {}", path.display());
+        }
         for (i, instance) in self.instances.iter().enumerate() {
             debug!("  + Instance: #{i}:");
             debug!("    - URL: {}", instance.base_url());
-            debug!(
-                "    - Token: {}",
-                instance.api_token().checkable_obfuscated()
-            );
+            debug!("    - Org: {:?}", instance.options().org);
+            debug!("    - Auth: {}", instance.auth().obfuscated());
+            for (name, _) in &instance.options().headers {
+                debug!("    - Header: {name}");
+            }
+            if instance.options().client_identity.is_some() {
+                debug!("    - Client Certificate: yes");
+            }
+            let tls = &instance.options().tls;
+            debug!("    - Extra CA Bundles: {}", tls.ca_bundles.len());
+            if let Some(version) = tls.min_version {
+                debug!("    - Min TLS Version: {:?}", version);
+            }
+            if tls.insecure_skip_verify {
+                debug!("    - TLS Verification: DISABLED");
+            }
+            if let Some(proxy) = &instance.options().proxy {
+                debug!("    - Proxy: {}", proxy.url.checkable_obfuscated());
+                debug!("    - No Proxy: {}", proxy.no_proxy.join(", "));
+            }
+            debug!("    - Max Concurrency: {}", instance.options().max_concurrency);
+            debug!("    - Partial View: {}", instance.options().partial_view);
+            for entry in &instance.options().datasources {
+                debug!("    - Datasource: {:?}", entry);
+            }
+            debug!("    - Transforms: {}", instance.options().transforms.len());
+            for (name, secrets) in &instance.options().contact_point_secrets {
+                let settings = secrets.keys().map(String::as_str).collect::<Vec<_>>();
+                debug!("    - Contact Point Secrets: {name} ({})", settings.join(", "));
+            }
+            for blackout in &instance.options().blackouts {
+                debug!("    - Blackout: {} to {}", blackout.from, blackout.to);
+            }
+        }
+    }
+
+    /// What changed compared to `previous`, for logging a reload. Secrets are compared,
+    /// but never part of the description.
+    pub(crate) fn differences(&self, previous: &Config) -> Vec<String> {
+        let mut differences = [
+            Self::changed("sync_tag", &previous.sync_tag, &self.sync_tag),
+            Self::changed("sync_groups", &previous.selector, &self.selector),
+            Self::changed("schedule", &previous.schedule, &self.schedule),
+            Self::changed("cache_file", &previous.cache_file, &self.cache_file),
+            Self::changed("shutdown_timeout", &previous.shutdown_timeout, &self.shutdown_timeout),
+            Self::changed("untag_policy", &previous.untag_policy, &self.untag_policy),
+            Self::changed(
+                "missing_datasources",
+                &previous.missing_datasources,
+                &self.missing_datasources,
+            ),
+            Self::changed(
+                "missing_panel_plugins",
+                &previous.missing_panel_plugins,
+                &self.missing_panel_plugins,
+            ),
+            Self::changed("alert_rules", &previous.alert_rules, &self.alert_rules),
+            Self::changed("notifications", &previous.notifications, &self.notifications),
+            Self::changed("displays", &previous.displays, &self.displays),
+            Self::changed("annotations", &previous.annotations, &self.annotations),
+            Self::changed("leader_election", &previous.leader_election, &self.leader_election),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        // the secret differs in every Debug output
+        if previous.trigger != self.trigger {
+            differences.push("trigger: changed".to_owned());
+        }
+
+        for instance in &previous.instances {
+            match self.instances.iter().find(|i| i.name() == instance.name()) {
+                None => differences.push(format!("removed instance {}", instance.name())),
+                Some(new) if Self::instance_settings(new) != Self::instance_settings(instance) => {
+                    differences.push(format!("changed settings of instance {}", instance.name()))
+                }
+                Some(_) => {}
+            }
+        }
+        for instance in &self.instances {
+            if !previous.instances.iter().any(|i| i.name() == instance.name()) {
+                differences.push(format!("added instance {}", instance.name()));
+            }
+        }
+
+        differences
+    }
+
+    fn changed<T: Debug>(key: &str, old: &T, new: &T) -> Option<String> {
+        let (old, new) = (format!("{old:?}"), format!("{new:?}"));
+        (old != new).then(|| format!("{key}: {old} -> {new}"))
+    }
+
+    /// Everything configured for an instance, only meant to be compared.
+    fn instance_settings(instance: &GrafanaInstance) -> Vec<String> {
+        let options = instance.options();
+        let mut settings = vec![
+            instance.auth().obfuscated(),
+            match instance.auth() {
+                Auth::Bearer(token) => token.value(),
+                Auth::Basic { password, .. } => password.value(),
+                Auth::OAuth2(config) => format!(
+                    "{:?} {:?} {}",
+                    config.scopes,
+                    config.audience,
+                    config.client_secret.value()
+                ),
+                Auth::None => String::new(),
+            },
+            format!("{:?} {:?}", options.org, options.org_names),
+            options.max_concurrency.to_string(),
+            format!("partial_view: {}", options.partial_view),
+            format!("{:?}", options.tls),
+            format!("{:?}", options.datasources),
+            format!("{:?}", options.transforms),
+            format!("{:?}", options.blackouts),
+        ];
+        for (name, value) in &options.headers {
+            settings.push(format!("{name}: {}", value.value()));
+        }
+        if let Some(identity) = &options.client_identity {
+            settings.push(identity.cert.value() + &identity.key.value());
+        }
+        if let Some(proxy) = &options.proxy {
+            settings.push(format!("{} {:?}", proxy.url.value(), proxy.no_proxy));
+        }
+        for (name, secrets) in &options.contact_point_secrets {
+            for (key, value) in secrets {
+                settings.push(format!("{name}.{key}: {}", value.value()));
+            }
         }
+        settings.sort();
+        settings
     }
 }
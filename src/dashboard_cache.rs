@@ -0,0 +1,287 @@
+use crate::annotation_state::KnownCopy;
+use crate::api::dashboards::{FullDashboard, SimpleDashboard};
+use crate::error::GSError;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Written next to the config file when no cache file is configured.
+pub const STATE_FILE_NAME: &str = "grafana-sync-state.json";
+
+type InstanceName = String;
+type Uid          = String;
+
+/// Remembers the last full dashboard seen per instance and UID, so that unchanged
+/// dashboards don't need to be downloaded again in the next cycle.
+///
+/// It also is the memory of which UIDs were synced on each instance, which were detached
+/// from the sync and on which instances they aren't selected, and of the annotations which
+/// existed on all instances after the last cycle. That memory is always persisted, the
+/// dashboards only with a cache file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DashboardCache {
+    #[serde(default)]
+    instances: HashMap<InstanceName, HashMap<Uid, FullDashboard>>,
+    // UIDs synced on each instance in the last cycle
+    #[serde(default)]
+    synced: HashMap<InstanceName, HashSet<Uid>>,
+    #[serde(default)]
+    detached: HashMap<Uid, HashSet<InstanceName>>,
+    // dashboard uid -> copies of its annotations
+    #[serde(default)]
+    annotations: HashMap<Uid, HashSet<KnownCopy>>,
+    // (instance, contact point) -> digest of the secrets last written, kept in memory only
+    #[serde(skip)]
+    contact_point_secrets: HashMap<(InstanceName, String), u64>,
+    #[serde(skip)]
+    path: PathBuf,
+    // whether the dashboards are written to `path` as well
+    #[serde(skip)]
+    with_dashboards: bool,
+}
+
+/// What is written to the state file when no cache file is configured.
+#[derive(Serialize)]
+struct CacheState<'a> {
+    synced: &'a HashMap<InstanceName, HashSet<Uid>>,
+    detached: &'a HashMap<Uid, HashSet<InstanceName>>,
+    annotations: &'a HashMap<Uid, HashSet<KnownCopy>>,
+}
+
+impl DashboardCache {
+
+    /* Constructors */
+
+    /// Creates a cache backed by `cache_file`, or by `state_file` without the dashboards,
+    /// which is read now and written back by [`DashboardCache::persist`].
+    /// A broken file is ignored.
+    pub fn new(cache_file: Option<PathBuf>, state_file: PathBuf) -> Self {
+        let with_dashboards = cache_file.is_some();
+        let path = cache_file.unwrap_or(state_file);
+        let mut cache = match Self::load(&path) {
+            Ok(cache) => cache.unwrap_or_default(),
+            Err(e) => {
+                warn!("Ignoring unreadable dashboard cache {}: {e}", path.display());
+                Self::default()
+            }
+        };
+        // cache files written before the synced UIDs were kept on their own
+        for (instance, dashboards) in &cache.instances {
+            cache
+                .synced
+                .entry(instance.clone())
+                .or_insert_with(|| dashboards.keys().cloned().collect());
+        }
+        if !with_dashboards {
+            cache.instances.clear();
+        }
+        cache.path = path;
+        cache.with_dashboards = with_dashboards;
+        cache
+    }
+
+    fn load(path: &Path) -> Result<Option<Self>, GSError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        let cache: Self = serde_json::from_reader(reader)?;
+        info!(
+            "Loaded dashboard cache with {} entries from {}",
+            cache.len(),
+            path.display()
+        );
+        Ok(Some(cache))
+    }
+
+    /* Public API */
+
+    /// Returns the cached dashboard if the listing shows it unchanged, judged by the version
+    /// or, failing that, by the update time. `None` if the listing has neither.
+    pub fn get_listed(&self, instance: &str, listed: &SimpleDashboard) -> Option<&FullDashboard> {
+        let cached = self.instances.get(instance)?.get(&listed.uid)?;
+        match (listed.version, listed.updated) {
+            (Some(version), _) => (cached.meta.version == version).then_some(cached),
+            (None, Some(updated)) => (cached.meta.updated == updated).then_some(cached),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the cached dashboard if it still is at `version` on the instance.
+    pub fn get(&self, instance: &str, uid: &str, version: i64) -> Option<&FullDashboard> {
+        self.instances
+            .get(instance)?
+            .get(uid)
+            .filter(|d| d.meta.version == version)
+    }
+
+    /// Replaces everything known about an instance with the dashboards of the current cycle,
+    /// dropping UIDs which are no longer part of the sync.
+    pub fn replace_instance(&mut self, instance: &str, dashboards: &[FullDashboard]) {
+        let entries = dashboards
+            .iter()
+            .map(|d| (d.dashboard.uid.clone(), d.clone()))
+            .collect();
+        self.instances.insert(instance.to_owned(), entries);
+        let uids = dashboards.iter().map(|d| d.dashboard.uid.clone()).collect();
+        self.synced.insert(instance.to_owned(), uids);
+    }
+
+    /// UIDs which were synced on `instance` in the last cycle and still exist there,
+    /// but aren't selected anymore, e.g. because their sync tag was removed.
+    pub fn deselected<'a>(&self, instance: &str, unselected: &'a HashSet<String>) -> Vec<&'a str> {
+        let Some(previous) = self.synced.get(instance) else {
+            return vec![];
+        };
+        unselected
+            .iter()
+            .filter(|uid| previous.contains(uid.as_str()))
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn detach(&mut self, uid: &str, instance: &str) {
+        self.detached
+            .entry(uid.to_owned())
+            .or_default()
+            .insert(instance.to_owned());
+    }
+
+    pub fn is_detached(&self, uid: &str) -> bool {
+        self.detached.contains_key(uid)
+    }
+
+    /// Takes UIDs back into the sync once any instance on which they weren't selected
+    /// selects them again. Detached UIDs which lost their selection on `instance` meanwhile
+    /// are remembered, so that selecting them there again counts as well.
+    pub fn reattach(
+        &mut self,
+        instance: &str,
+        selected: &[FullDashboard],
+        unselected: &HashSet<String>,
+    ) -> Vec<Uid> {
+        let uids = selected
+            .iter()
+            .map(|d| &d.dashboard.uid)
+            .filter(|uid| {
+                self.detached
+                    .get(uid.as_str())
+                    .is_some_and(|instances| instances.contains(instance))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for uid in &uids {
+            self.detached.remove(uid);
+        }
+
+        for (uid, instances) in &mut self.detached {
+            if unselected.contains(uid) {
+                instances.insert(instance.to_owned());
+            }
+        }
+        uids
+    }
+
+    pub fn known_annotations(&self, uid: &str) -> HashSet<KnownCopy> {
+        self.annotations.get(uid).cloned().unwrap_or_default()
+    }
+
+    pub fn set_known_annotations(&mut self, uid: &str, copies: HashSet<KnownCopy>) {
+        self.annotations.insert(uid.to_owned(), copies);
+    }
+
+    /// Forgets the annotations of dashboards which aren't synced anymore.
+    pub fn retain_annotations(&mut self, uids: &HashSet<&str>) {
+        self.annotations.retain(|uid, _| uids.contains(uid.as_str()));
+    }
+
+    pub fn secrets_digest(&self, instance: &str, contact_point: &str) -> Option<u64> {
+        self.contact_point_secrets
+            .get(&(instance.to_owned(), contact_point.to_owned()))
+            .copied()
+    }
+
+    pub fn set_secrets_digest(&mut self, instance: &str, contact_point: &str, digest: u64) {
+        self.contact_point_secrets
+            .insert((instance.to_owned(), contact_point.to_owned()), digest);
+    }
+
+    /// Writes the cache to its backing file. The file is replaced atomically, so that a
+    /// crash while writing leaves the previous state instead of a broken file.
+    pub async fn persist(&self) -> Result<(), GSError> {
+        let json = match self.with_dashboards {
+            true => serde_json::to_vec(self)?,
+            false => serde_json::to_vec(&CacheState {
+                synced: &self.synced,
+                detached: &self.detached,
+                annotations: &self.annotations,
+            })?,
+        };
+        let temporary = self.path.with_extension(format!("{}.tmp", std::process::id()));
+        tokio::fs::write(&temporary, json).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+        debug!("Persisted dashboard cache to {}", self.path.display());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.instances.values().map(HashMap::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::dashboards::FullDashboardData;
+
+    fn dashboard(uid: &str) -> FullDashboard {
+        FullDashboard {
+            dashboard: FullDashboardData {
+                uid: uid.to_owned(),
+                ..Default::default()
+            },
+            meta: Default::default(),
+        }
+    }
+
+    fn state_file(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("grafana-sync-{test}-{}.json", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn untag_while_down_is_detected_after_restart() {
+        let path = state_file("untag-restart");
+        let mut cache = DashboardCache::new(None, path.clone());
+        cache.replace_instance("a", &[dashboard("x"), dashboard("y")]);
+        cache.persist().await.unwrap();
+
+        // 'x' lost its sync tag while the process was down
+        let mut restarted = DashboardCache::new(None, path.clone());
+        let unselected = HashSet::from(["x".to_owned()]);
+        assert_eq!(restarted.deselected("a", &unselected), vec!["x"]);
+        assert!(restarted.deselected("b", &unselected).is_empty());
+
+        restarted.detach("x", "a");
+        restarted.persist().await.unwrap();
+        let restarted = DashboardCache::new(None, path.clone());
+        std::fs::remove_file(&path).unwrap();
+        assert!(restarted.is_detached("x"));
+        assert!(!restarted.is_detached("y"));
+    }
+
+    #[tokio::test]
+    async fn state_file_keeps_uids_but_no_dashboards() {
+        let path = state_file("uids-only");
+        let mut cache = DashboardCache::new(None, path.clone());
+        cache.replace_instance("a", &[dashboard("x")]);
+        cache.persist().await.unwrap();
+
+        let restarted = DashboardCache::new(None, path.clone());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restarted.len(), 0);
+        assert_eq!(restarted.synced["a"], HashSet::from(["x".to_owned()]));
+    }
+}
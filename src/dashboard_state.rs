@@ -1,9 +1,11 @@
 use crate::api::dashboards::FullDashboard;
+use crate::transform::Pipeline;
 use chrono::{DateTime, Local};
 use log::{debug, info, warn};
 use std::collections::{HashMap, HashSet};
 
-type Dashboards<'a> = HashMap<&'a str, Vec<&'a FullDashboard>>;
+// uid -> (set name, copy)
+type Dashboards<'a> = HashMap<&'a str, Vec<(&'a str, &'a FullDashboard)>>;
 type SetName        = String;
 
 #[derive(Debug, Clone)]
@@ -29,18 +31,29 @@ impl DashboardState {
 
     /* public API */
 
+    /// `mask` holds the transformations of all instances; whatever they touch is
+    /// ignored when deciding whether the copies of a dashboard are in sync.
+    /// Copies of the `transformed` sets only win if no other set has a copy, as
+    /// their transformations would spread to all instances otherwise.
     pub fn diff(
         &self,
         destructive: bool,
         sync_interval_mins: u64,
+        mask: &Pipeline,
+        transformed: &HashSet<&str>,
     ) -> Vec<(&str, Option<FullDashboard>)> {
         let by_uid = index_by_uid(&self.sets);
+        let settings = MergeSettings {
+            destructive,
+            sync_interval_mins,
+            instance_count: self.instance_count,
+            mask,
+            transformed,
+        };
 
         by_uid
             .into_iter()
-            .filter_map(|(uid, dashboards)| {
-                merge_dashboards(uid, &dashboards, destructive, sync_interval_mins, self.instance_count)
-            })
+            .filter_map(|(uid, dashboards)| merge_dashboards(uid, &dashboards, &settings))
             .collect()
     }
 
@@ -52,6 +65,20 @@ impl DashboardState {
             .collect()
     }
 
+    pub fn dashboard_uids(&self) -> HashSet<&str> {
+        self.sets
+            .values()
+            .flat_map(|v| v.iter().map(|d| d.dashboard.uid.as_str()))
+            .collect()
+    }
+
+    pub fn library_panel_uids(&self) -> HashSet<&str> {
+        self.sets
+            .values()
+            .flat_map(|v| v.iter().flat_map(|d| d.dashboard.library_panel_uids()))
+            .collect()
+    }
+
     pub fn print_data_stats(&self) {
         for (name, dbs) in &self.sets {
             info!("{name}: {} sync dashboards", dbs.len());
@@ -61,32 +88,34 @@ impl DashboardState {
 
 fn index_by_uid(sets: &HashMap<SetName, Vec<FullDashboard>>) -> Dashboards<'_> {
     let mut map: Dashboards = HashMap::new();
-    for dashboards in sets.values() {
+    for (name, dashboards) in sets {
         for d in dashboards {
-            map.entry(d.dashboard.uid.as_str()).or_default().push(d);
+            map.entry(d.dashboard.uid.as_str()).or_default().push((name.as_str(), d));
         }
     }
     map
 }
 
+struct MergeSettings<'a> {
+    destructive: bool,
+    sync_interval_mins: u64,
+    instance_count: usize,
+    mask: &'a Pipeline,
+    transformed: &'a HashSet<&'a str>,
+}
+
 /// Decide whether the dashboards with the same UID are **all** identical.
 /// If not, determine which concrete dashboard should “win”.
 fn merge_dashboards<'a>(
     uid: &'a str,
-    dashboards: &[&FullDashboard],
-    destructive: bool,
-    sync_interval_mins: u64,
-    instance_count: usize,
+    copies: &[(&str, &FullDashboard)],
+    settings: &MergeSettings,
 ) -> Option<(&'a str, Option<FullDashboard>)> {
+    let dashboards = copies.iter().map(|(_, d)| *d).collect::<Vec<_>>();
     debug!("{uid}: {:?}", dashboards.iter().map(|d| &d.dashboard.title).collect::<Vec<_>>());
-    let first = dashboards.first()?;
 
     // Fast track: If all dashboards are synced already
-    if dashboards.len() == instance_count 
-        && dashboards
-        .iter()
-        .all(|d| dashboards_equal(first, d))
-    {
+    if dashboards.len() == settings.instance_count && all_equal(&dashboards, settings.mask) {
         return None;
     }
 
@@ -99,18 +128,57 @@ fn merge_dashboards<'a>(
     // Delete if matching criteria to determine it was deleted
     let now: DateTime<Local> = Local::now();
     let age_mins: u64        = (now - newest.meta.updated).num_minutes() as u64;
-    let delete_outdated      = destructive && age_mins > sync_interval_mins * 2;
+    let delete_outdated      = settings.destructive && age_mins > settings.sync_interval_mins * 2;
+
+    // A transformed copy is usually the newest, as its import updated it
+    let untransformed = copies
+        .iter()
+        .filter(|(name, _)| !settings.transformed.contains(name))
+        .map(|(_, d)| *d)
+        .max_by_key(|d| d.meta.updated);
+    let winner = untransformed.unwrap_or_else(|| {
+        warn!("{uid}: only transformed copies exist, syncing the newest one as it is");
+        newest
+    });
 
     let result = if delete_outdated {
         warn!("Dashboard {} will be deleted", newest.dashboard.title);
         None
     } else {
-        Some(newest.clone())
+        Some(winner.clone())
     };
 
     Some((uid, result))
 }
 
+fn all_equal(dashboards: &[&FullDashboard], mask: &Pipeline) -> bool {
+    let Some(first) = dashboards.first() else {
+        return true;
+    };
+
+    if mask.is_empty() {
+        return dashboards.iter().all(|d| dashboards_equal(first, d));
+    }
+
+    let first = masked_comparable(first, mask);
+    dashboards
+        .iter()
+        .all(|d| masked_comparable(d, mask) == first)
+}
+
+/// The fields compared by [`dashboards_equal`], with the transformations masked out.
+fn masked_comparable(d: &FullDashboard, mask: &Pipeline) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "uid": d.dashboard.uid,
+        "title": d.dashboard.title,
+        "tags": d.dashboard.tags,
+        "panels": d.dashboard.panels,
+        "graphTooltip": d.dashboard.graph_tooltip,
+    });
+    mask.mask(&mut value);
+    value
+}
+
 #[inline]
 fn dashboards_equal(a: &FullDashboard, b: &FullDashboard) -> bool {
     a.dashboard.uid == b.dashboard.uid
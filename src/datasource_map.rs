@@ -0,0 +1,225 @@
+use crate::api::dashboards::FullDashboard;
+use crate::api::datasources::Datasource;
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+// References which don't point to a datasource of their own
+const BUILTIN_DATASOURCES: &[&str] = &[
+    "default",
+    "grafana",
+    "dashboard",
+    "-- Grafana --",
+    "-- Mixed --",
+    "-- Dashboard --",
+];
+
+/// One entry of an instance's `datasources` list. `shared` is the identifier used for the
+/// datasource while comparing dashboards; the local datasource is found by `uid`,
+/// or by `name` (optionally narrowed down by `type`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatasourceMapEntry {
+    pub shared: String,
+    pub uid: Option<String>,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+}
+
+impl DatasourceMapEntry {
+    fn needs_lookup(&self) -> bool {
+        self.uid.is_none()
+    }
+
+    fn resolve<'a>(&'a self, datasources: &'a [Datasource]) -> Option<&'a str> {
+        if let Some(uid) = &self.uid {
+            return Some(uid);
+        }
+        let name = self.name.as_deref()?;
+        datasources
+            .iter()
+            .filter(|ds| ds.name == name)
+            .find(|ds| self.type_name.as_ref().is_none_or(|t| *t == ds.type_name))
+            .map(|ds| ds.uid.as_str())
+    }
+}
+
+/// Resolved datasource mapping of one instance. Dashboards are compared in their
+/// *shared* form and converted to the *local* form right before they're imported.
+///
+/// If asked to, it also knows which datasources exist on the instance.
+#[derive(Debug, Clone, Default)]
+pub struct DatasourceMap {
+    to_local: HashMap<String, String>,
+    to_shared: HashMap<String, String>,
+    // uids and names of the instance's datasources
+    available: Option<HashSet<String>>,
+}
+
+impl DatasourceMap {
+    /// Resolves the configured entries of an instance, looking up datasources by name if needed.
+    /// With `validate`, the instance's datasources are always listed to check references later,
+    /// unless the token isn't allowed to list them.
+    pub async fn resolve(instance: &GrafanaInstance, mut validate: bool) -> Result<Self, GSError> {
+        let entries = &instance.options().datasources;
+        let needs_lookup = entries.iter().any(DatasourceMapEntry::needs_lookup);
+
+        let datasources = if validate || needs_lookup {
+            match instance.get_datasources().await {
+                Err(e) if !needs_lookup && e.is_forbidden() => {
+                    warn!(
+                        "{}: not allowed to list datasources, not checking them",
+                        instance.name()
+                    );
+                    validate = false;
+                    vec![]
+                }
+                res => res?,
+            }
+        } else {
+            vec![]
+        };
+
+        let mut map = Self::default();
+        if validate {
+            map.available = Some(
+                datasources
+                    .iter()
+                    .flat_map(|ds| [ds.uid.clone(), ds.name.clone()])
+                    .collect(),
+            );
+        }
+
+        for entry in entries {
+            let local = entry.resolve(&datasources).ok_or_else(|| {
+                GSError::DatasourceNotFound(instance.name().to_owned(), entry.shared.clone())
+            })?;
+            map.to_local.insert(entry.shared.clone(), local.to_owned());
+            map.to_shared.insert(local.to_owned(), entry.shared.clone());
+        }
+        Ok(map)
+    }
+
+    /// Replaces the instance's own datasource UIDs with their shared identifiers.
+    pub fn to_shared(&self, dashboard: &mut FullDashboard) {
+        rewrite_dashboard(dashboard, &self.to_shared);
+    }
+
+    /// Replaces shared identifiers with the instance's own datasource UIDs.
+    pub fn to_local(&self, dashboard: &mut FullDashboard) {
+        rewrite_dashboard(dashboard, &self.to_local);
+    }
+
+    /// Like [`DatasourceMap::to_shared`], for any other model such as a library panel.
+    pub fn value_to_shared(&self, value: &mut Value) {
+        rewrite_value(value, &self.to_shared);
+    }
+
+    /// Like [`DatasourceMap::to_local`], for any other model such as a library panel.
+    pub fn value_to_local(&self, value: &mut Value) {
+        rewrite_value(value, &self.to_local);
+    }
+
+    /// Datasources a (local form) dashboard references, but which don't exist on the instance.
+    /// Always empty if the map wasn't resolved with validation.
+    pub fn missing_datasources(&self, dashboard: &FullDashboard) -> Vec<String> {
+        let Some(available) = &self.available else {
+            return vec![];
+        };
+
+        let data = &dashboard.dashboard;
+        let mut references = HashSet::new();
+        data.panels
+            .iter()
+            .for_each(|p| collect_references(p, &mut references));
+        collect_references(&data.templating, &mut references);
+        collect_references(&data.annotations, &mut references);
+
+        let mut missing = references
+            .into_iter()
+            .filter(|r| !available.contains(r))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing
+    }
+}
+
+fn rewrite_dashboard(dashboard: &mut FullDashboard, map: &HashMap<String, String>) {
+    if map.is_empty() {
+        return;
+    }
+    let data = &mut dashboard.dashboard;
+    data.panels.iter_mut().for_each(|p| rewrite_value(p, map));
+    rewrite_value(&mut data.templating, map);
+    rewrite_value(&mut data.annotations, map);
+}
+
+/// Walks a dashboard JSON tree and rewrites every `datasource` reference, be it the
+/// `{ "type": .., "uid": .. }` object or the legacy plain string. Alert rule queries
+/// reference theirs by `datasourceUid`.
+fn rewrite_value(value: &mut Value, map: &HashMap<String, String>) {
+    match value {
+        Value::Object(obj) => {
+            for (key, child) in obj.iter_mut() {
+                if key == "datasource" || key == "datasourceUid" {
+                    rewrite_reference(child, map);
+                } else {
+                    rewrite_value(child, map);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| rewrite_value(v, map)),
+        _ => {}
+    }
+}
+
+fn rewrite_reference(reference: &mut Value, map: &HashMap<String, String>) {
+    let uid = match reference {
+        Value::String(uid) => uid,
+        Value::Object(obj) => match obj.get_mut("uid") {
+            Some(Value::String(uid)) => uid,
+            _ => return,
+        },
+        _ => return,
+    };
+    if let Some(mapped) = map.get(uid.as_str()) {
+        *uid = mapped.clone();
+    }
+}
+
+fn collect_references(value: &Value, references: &mut HashSet<String>) {
+    match value {
+        Value::Object(obj) => {
+            for (key, child) in obj {
+                if key == "datasource" {
+                    references.extend(reference_uid(child));
+                } else {
+                    collect_references(child, references);
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_references(v, references)),
+        _ => {}
+    }
+}
+
+/// The uid (or legacy name) of a reference, unless it is a template variable or built in.
+fn reference_uid(reference: &Value) -> Option<String> {
+    let uid = match reference {
+        Value::String(uid) => uid.as_str(),
+        Value::Object(obj) => {
+            if obj.get("type").and_then(Value::as_str) == Some("datasource") {
+                return None;
+            }
+            obj.get("uid")?.as_str()?
+        }
+        _ => return None,
+    };
+
+    let builtin = uid.starts_with('$') || BUILTIN_DATASOURCES.contains(&uid);
+    (!builtin).then(|| uid.to_owned())
+}
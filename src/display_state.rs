@@ -0,0 +1,109 @@
+use crate::api::playlists::Playlist;
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+type SetName = String;
+
+/// What an instance's wall displays show: its playlists and the org's home dashboard.
+#[derive(Debug, Clone, Default)]
+pub struct DisplaySet {
+    pub playlists: Vec<Playlist>,
+    pub home_dashboard: Option<String>,
+}
+
+/// What has to be written to one instance.
+#[derive(Debug, Clone)]
+pub struct DisplayUpdate<'a> {
+    pub target: &'a str,
+    // (source copy, exists on the target)
+    pub playlists: Vec<(&'a Playlist, bool)>,
+    pub home_dashboard: Option<&'a str>,
+}
+
+/// Playlists and home dashboards of all instances. Like notification settings, they carry no
+/// modification time, so one instance is the source for all others.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayState {
+    sets: HashMap<SetName, DisplaySet>,
+}
+
+impl DisplayState {
+    /* Constructors */
+
+    pub fn add_set(&mut self, base_url: String, set: DisplaySet) {
+        self.sets.insert(base_url, set);
+    }
+
+    /* public API */
+
+    /// Lists the source's playlists and home dashboard where they differ on other instances.
+    /// Only playlists made up entirely of synced dashboards and tags are considered, as well as
+    /// a home dashboard that is synced. Nothing is ever deleted.
+    pub fn diff(
+        &self,
+        source: &str,
+        synced_uids: &HashSet<&str>,
+        synced_tags: &HashSet<&str>,
+    ) -> Vec<DisplayUpdate<'_>> {
+        let Some(source_set) = self.sets.get(source) else {
+            return vec![];
+        };
+
+        let playlists = source_set
+            .playlists
+            .iter()
+            .filter(|p| {
+                let portable = is_portable(p, synced_uids, synced_tags);
+                if !portable {
+                    debug!("playlist '{}' shows dashboards which aren't synced", p.name);
+                }
+                portable
+            })
+            .collect::<Vec<_>>();
+        let home_dashboard = source_set
+            .home_dashboard
+            .as_deref()
+            .filter(|uid| synced_uids.contains(uid));
+
+        self.sets
+            .iter()
+            .filter(|(name, _)| name.as_str() != source)
+            .map(|(name, set)| DisplayUpdate {
+                target: name.as_str(),
+                playlists: playlists
+                    .iter()
+                    .copied()
+                    .filter_map(|p| match set.playlists.iter().find(|o| o.uid == p.uid) {
+                        Some(o) if o == p => None,
+                        other => Some((p, other.is_some())),
+                    })
+                    .collect(),
+                home_dashboard: home_dashboard
+                    .filter(|&uid| set.home_dashboard.as_deref() != Some(uid)),
+            })
+            .filter(|u| !u.playlists.is_empty() || u.home_dashboard.is_some())
+            .collect()
+    }
+
+    pub fn print_data_stats(&self) {
+        for (name, set) in &self.sets {
+            debug!("{name}: {} playlists", set.playlists.len());
+        }
+    }
+}
+
+fn is_portable(
+    playlist: &Playlist,
+    synced_uids: &HashSet<&str>,
+    synced_tags: &HashSet<&str>,
+) -> bool {
+    !playlist.items.is_empty()
+        && playlist
+            .items
+            .iter()
+            .all(|item| match item.type_name.as_str() {
+                "dashboard_by_uid" => synced_uids.contains(item.value.as_str()),
+                "dashboard_by_tag" => synced_tags.contains(item.value.as_str()),
+                _ => false,
+            })
+}
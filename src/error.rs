@@ -1,4 +1,4 @@
-use reqwest::header::InvalidHeaderValue;
+use reqwest::header::{InvalidHeaderName, InvalidHeaderValue};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,14 +11,42 @@ pub enum GSError {
     ConfigKeyMissing(String),
     #[error("The config was invalid. Key \"{0}\" was not of type \"{1}\".")]
     ConfigKeyTypeWrong(String, &'static str),
+    #[error("The sync group \"{0}\" is invalid: {1}")]
+    InvalidSelector(String, String),
+    #[error("The config section \"{0}\" is invalid: {1}")]
+    InvalidSetting(String, String),
+    #[error("A dashboard transformation is invalid: {0}")]
+    InvalidTransform(String),
+    #[error("The sync schedule is invalid: {0}")]
+    InvalidSchedule(String),
+    #[error("Invalid command line: {0}")]
+    InvalidArguments(String),
     #[error(transparent)]
     RequestError(#[from] reqwest::Error),
     #[error(
         "The provided static header value was invalid. This is most likely a configuration error."
     )]
     InvalidHeader(#[from] InvalidHeaderValue),
+    #[error("A configured header name was invalid.")]
+    InvalidHeaderName(#[from] InvalidHeaderName),
     #[error(transparent)]
     JSONError(#[from] serde_json::error::Error),
     #[error(transparent)]
     JoinError(#[from] tokio::task::JoinError),
+    #[error("{0}: no datasource found for the shared datasource \"{1}\".")]
+    DatasourceNotFound(String, String),
+    #[error("{0}: dashboard \"{1}\" needs missing {2}: {3}")]
+    MissingDependencies(String, String, &'static str, String),
+    #[error("The listing of {0} appears to be truncated: {1}")]
+    TruncatedListing(String, String),
+}
+
+impl GSError {
+    /// Whether Grafana refused the request for lack of permissions.
+    pub fn is_forbidden(&self) -> bool {
+        matches!(
+            self,
+            GSError::RequestError(e) if e.status() == Some(reqwest::StatusCode::FORBIDDEN)
+        )
+    }
 }
@@ -1,55 +1,362 @@
+use crate::datasource_map::DatasourceMapEntry;
 use crate::encrypted_cred::EncryptedCredential;
 use crate::error::GSError;
-use reqwest::header::{HeaderMap, HeaderValue};
+use crate::schedule::Blackout;
+use crate::transform::Pipeline;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use log::warn;
+use std::collections::HashMap;
+use crate::oauth::{AccessToken, OAuth2Config, OAuth2Session};
+use reqwest::StatusCode;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
 use tracing::instrument;
 
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+/// Which Grafana organisation(s) of a server an instance stands for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrgSelection {
+    /// The org the credentials belong to.
+    #[default]
+    Current,
+    /// One specific org, selected by the `X-Grafana-Org-Id` header.
+    Id(u64),
+    /// Every org of the server, each synced with the same-named orgs of other servers.
+    All,
+}
+
+/// How requests to an instance are authenticated.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// `Authorization: Bearer <token>`, e.g. with a service account token.
+    Bearer(EncryptedCredential),
+    /// HTTP basic auth, e.g. for a Grafana user or an auth proxy.
+    Basic {
+        username: String,
+        password: EncryptedCredential,
+    },
+    /// Bearer tokens obtained with the OAuth2 client credentials flow, renewed as needed.
+    OAuth2(OAuth2Config),
+    /// No `Authorization` header, for proxies relying on client certificates or headers.
+    None,
+}
+
+impl Auth {
+    /// A description safe to log.
+    pub fn obfuscated(&self) -> String {
+        match self {
+            Auth::Bearer(token) => format!("Bearer {}", token.checkable_obfuscated()),
+            Auth::Basic { username, .. } => format!("Basic {username}:***"),
+            Auth::OAuth2(config) => format!("OAuth2 {} via {}", config.client_id, config.token_url),
+            Auth::None => "None".to_owned(),
+        }
+    }
+}
+
+/// Client certificate and key (both PEM, the key in PKCS#8) presented for mutual TLS.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub cert: EncryptedCredential,
+    pub key: EncryptedCredential,
+}
+
+/// TLS settings of an instance on top of the system's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    // PEM bundles trusted in addition to the system's roots
+    pub ca_bundles: Vec<Vec<u8>>,
+    pub min_version: Option<reqwest::tls::Version>,
+    pub insecure_skip_verify: bool,
+}
+
+/// HTTP(S) or SOCKS5 proxy an instance is reached through.
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+    // may contain credentials
+    pub url: EncryptedCredential,
+    // hosts, domains or networks reached directly
+    pub no_proxy: Vec<String>,
+}
+
+/// Per-instance settings besides how to reach and authenticate against the instance.
+#[derive(Debug, Clone)]
+pub struct InstanceOptions {
+    pub max_concurrency: usize,
+    // extra headers sent with every request, the values may be secrets
+    pub headers: Vec<(String, EncryptedCredential)>,
+    pub client_identity: Option<ClientIdentity>,
+    pub tls: TlsOptions,
+    pub proxy: Option<ProxyOptions>,
+    pub org: OrgSelection,
+    // local org name -> name it is matched by across servers, for `OrgSelection::All`
+    pub org_names: HashMap<String, String>,
+    pub datasources: Vec<DatasourceMapEntry>,
+    pub transforms: Pipeline,
+    // contact point name -> secure setting -> value written on this instance
+    pub contact_point_secrets: HashMap<String, HashMap<String, EncryptedCredential>>,
+    // periods during which the instance sits out the cycles
+    pub blackouts: Vec<Blackout>,
+    // the token may not view every tagged dashboard, so tag counts may exceed the listing
+    pub partial_view: bool,
+}
+
+impl Default for InstanceOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            headers: vec![],
+            client_identity: None,
+            tls: TlsOptions::default(),
+            proxy: None,
+            org: OrgSelection::Current,
+            org_names: HashMap::new(),
+            datasources: vec![],
+            transforms: Pipeline::default(),
+            contact_point_secrets: HashMap::new(),
+            blackouts: vec![],
+            partial_view: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GrafanaInstance {
     url: String,
-    api_token: EncryptedCredential,
-    http_client: reqwest::Client,
+    // identifies the instance in states, caches and logs: the url, plus the org if selected
+    name: String,
+    // name of the org as matched across servers, for instances expanded from all orgs
+    shared_org: Option<String>,
+    auth: Auth,
+    // replaced whenever a new OAuth2 token is put to use
+    http_client: Arc<RwLock<reqwest::Client>>,
+    oauth: Option<Arc<OAuth2Session>>,
+    options: InstanceOptions,
+    request_slots: Arc<Semaphore>,
 }
 
 impl GrafanaInstance {
-    fn _make_new_client(api_token: &EncryptedCredential) -> Result<reqwest::Client, GSError> {
+    fn _make_new_client(
+        auth: &Auth,
+        token: Option<&AccessToken>,
+        options: &InstanceOptions,
+    ) -> Result<reqwest::Client, GSError> {
         let mut header_map = HeaderMap::new();
-        header_map.insert(
-            "Authorization",
-            HeaderValue::try_from(format!("Bearer {}", api_token.value()))?,
-        );
+        for (name, value) in &options.headers {
+            let mut value = HeaderValue::try_from(value.value())?;
+            value.set_sensitive(true);
+            header_map.insert(HeaderName::try_from(name.as_str())?, value);
+        }
+
+        let authorization = match auth {
+            Auth::Bearer(token) => Some(format!("Bearer {}", token.value())),
+            Auth::Basic { username, password } => Some(format!(
+                "Basic {}",
+                BASE64.encode(format!("{username}:{}", password.value()))
+            )),
+            Auth::OAuth2(_) => token.map(|t| format!("Bearer {}", t.value.value())),
+            Auth::None => None,
+        };
+        if let Some(authorization) = authorization {
+            let mut value = HeaderValue::try_from(authorization)?;
+            value.set_sensitive(true);
+            header_map.insert(AUTHORIZATION, value);
+        }
+
+        if let OrgSelection::Id(id) = options.org {
+            header_map.insert("X-Grafana-Org-Id", HeaderValue::from(id));
+        }
         header_map.insert("accept", HeaderValue::from_static("application/json"));
 
-        let client = reqwest::Client::builder()
-            .default_headers(header_map)
-            .user_agent(concat!(
-                env!("CARGO_PKG_NAME"),
-                "/",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()?;
+        let builder = Self::_make_client_builder(options)?.default_headers(header_map);
+        Ok(builder.build()?)
+    }
+
+    /// Everything about the connection: TLS, client certificate and proxy.
+    fn _make_client_builder(options: &InstanceOptions) -> Result<reqwest::ClientBuilder, GSError> {
+        let mut builder = reqwest::Client::builder().user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ));
+        if let Some(identity) = &options.client_identity {
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(
+                identity.cert.value().as_bytes(),
+                identity.key.value().as_bytes(),
+            )?);
+        }
+
+        for bundle in &options.tls.ca_bundles {
+            for cert in reqwest::Certificate::from_pem_bundle(bundle)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(version) = options.tls.min_version {
+            builder = builder.min_tls_version(version);
+        }
+        if options.tls.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
 
-        Ok(client)
+        if let Some(proxy) = &options.proxy {
+            let no_proxy = reqwest::NoProxy::from_string(&proxy.no_proxy.join(","));
+            builder = builder.proxy(reqwest::Proxy::all(proxy.url.value())?.no_proxy(no_proxy));
+        }
+
+        Ok(builder)
+    }
+
+    fn _make_oauth_session(
+        auth: &Auth,
+        options: &InstanceOptions,
+    ) -> Result<Option<Arc<OAuth2Session>>, GSError> {
+        let Auth::OAuth2(config) = auth else {
+            return Ok(None);
+        };
+        let client = Self::_make_client_builder(options)?.build()?;
+        Ok(Some(Arc::new(OAuth2Session::new(config.clone(), client))))
     }
 
-    pub fn new(url: String, api_token: EncryptedCredential) -> Result<Self, GSError> {
-        let http_client = Self::_make_new_client(&api_token)?;
+    pub fn new(
+        url: String,
+        auth: Auth,
+        mut options: InstanceOptions,
+    ) -> Result<Self, GSError> {
+        let http_client = Self::_make_new_client(&auth, None, &options)?;
+        let oauth = Self::_make_oauth_session(&auth, &options)?;
+        options.max_concurrency = options.max_concurrency.max(1);
+        let name = Self::_make_name(&url, options.org);
+        Self::_warn_if_insecure(&name, &options);
         Ok(GrafanaInstance {
+            name,
+            shared_org: None,
             url,
-            api_token,
-            http_client,
+            auth,
+            http_client: Arc::new(RwLock::new(http_client)),
+            oauth,
+            request_slots: Arc::new(Semaphore::new(options.max_concurrency)),
+            options,
+        })
+    }
+
+    /// The instance for one org of this instance's server, as listed by [`GrafanaInstance::get_orgs`].
+    /// Both share their request slots, as they share the server.
+    pub fn with_org(&self, id: u64, org_name: &str) -> Result<Self, GSError> {
+        let mut options = self.options.clone();
+        options.org = OrgSelection::Id(id);
+        let shared_org = options
+            .org_names
+            .get(org_name)
+            .cloned()
+            .unwrap_or_else(|| org_name.to_owned());
+
+        let name = Self::_make_name(&self.url, options.org);
+        Self::_warn_if_insecure(&name, &options);
+        Ok(GrafanaInstance {
+            url: self.url.clone(),
+            name,
+            shared_org: Some(shared_org),
+            auth: self.auth.clone(),
+            http_client: Arc::new(RwLock::new(Self::_make_new_client(&self.auth, None, &options)?)),
+            oauth: Self::_make_oauth_session(&self.auth, &options)?,
+            options,
+            request_slots: self.request_slots.clone(),
         })
     }
 
+    fn _warn_if_insecure(name: &str, options: &InstanceOptions) {
+        if options.tls.insecure_skip_verify {
+            warn!(
+                "!!! {name}: TLS certificates are NOT verified, the connection can be intercepted !!!"
+            );
+        }
+    }
+
+    fn _make_name(url: &str, org: OrgSelection) -> String {
+        match org {
+            OrgSelection::Id(id) => format!("{url}?orgId={id}"),
+            _ => url.to_owned(),
+        }
+    }
+
     pub fn base_url(&self) -> &str {
         self.url.as_str()
     }
 
-    pub fn api_token(&self) -> &EncryptedCredential {
-        &self.api_token
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn shared_org(&self) -> Option<&str> {
+        self.shared_org.as_deref()
     }
 
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    pub fn options(&self) -> &InstanceOptions {
+        &self.options
+    }
+
+    /// The client to build requests with. Send them with [`GrafanaInstance::send`].
     #[instrument]
-    pub fn client(&self) -> &reqwest::Client {
-        &self.http_client
+    pub fn client(&self) -> reqwest::Client {
+        self.http_client
+            .read()
+            .expect("client lock is never poisoned")
+            .clone()
+    }
+
+    /// Sends a request built with [`GrafanaInstance::client`]. With OAuth2, a token is obtained
+    /// or renewed first if needed, and a request rejected with 401 is retried once with a new token.
+    pub async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, GSError> {
+        let Some(oauth) = &self.oauth else {
+            return Ok(request.send().await?);
+        };
+
+        self.refresh_token(oauth, None).await?;
+        let used = oauth.current().await;
+
+        // the client's default headers, and with them the token, are added on execution
+        let request = request.build()?;
+        let retry = request.try_clone();
+        let response = self.client().execute(request).await?;
+
+        match retry {
+            Some(retry) if response.status() == StatusCode::UNAUTHORIZED => {
+                if self.refresh_token(oauth, Some(used)).await? {
+                    warn!("{}: OAuth2 token was rejected, renewed it", self.name());
+                }
+                Ok(self.client().execute(retry).await?)
+            }
+            _ => Ok(response),
+        }
+    }
+
+    async fn refresh_token(
+        &self,
+        oauth: &OAuth2Session,
+        rejected: Option<Option<Instant>>,
+    ) -> Result<bool, GSError> {
+        oauth
+            .refresh(rejected, |token| {
+                let client = Self::_make_new_client(&self.auth, Some(token), &self.options)?;
+                *self.http_client.write().expect("client lock is never poisoned") = client;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Waits for one of the instance's request slots. All clones of an instance share
+    /// the same slots, so holding the permit bounds in-flight work against this Grafana.
+    pub async fn acquire_slot(&self) -> SemaphorePermit<'_> {
+        self.request_slots
+            .acquire()
+            .await
+            .expect("request semaphore is never closed")
     }
 }
@@ -0,0 +1,328 @@
+use crate::api::leases::Lease;
+use crate::error::GSError;
+use crate::instance::GrafanaInstance;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+
+const DEFAULT_LEASE_SECS: u64 = 60;
+// How long a replica waits after taking over a lock file before trusting it won
+const FILE_SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Where the lease is kept.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LeaseBackend {
+    /// A JSON file on storage shared by all replicas.
+    File { path: PathBuf },
+    /// The lease dashboard on an instance, the first one if unset.
+    Grafana {
+        #[serde(default)]
+        instance: Option<String>,
+    },
+}
+
+/// Lets only one of several replicas sync at a time.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LeaderElectionSettings {
+    #[serde(flatten)]
+    pub backend: LeaseBackend,
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+    // names this replica in the lease, host name and pid if unset
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+fn default_lease_secs() -> u64 {
+    DEFAULT_LEASE_SECS
+}
+
+#[derive(Debug)]
+enum Store {
+    File(PathBuf),
+    Grafana(Box<GrafanaInstance>),
+}
+
+/// Takes and renews the lease for this replica.
+#[derive(Debug)]
+pub struct LeaderElector {
+    store: Store,
+    identity: String,
+    lease: Duration,
+    // end of the lease this replica holds, if it leads
+    leading_until: Option<DateTime<Utc>>,
+}
+
+impl LeaderElector {
+
+    /* Constructors */
+
+    pub fn new(
+        settings: &LeaderElectionSettings,
+        instances: &[GrafanaInstance],
+    ) -> Result<Self, GSError> {
+        let store = match &settings.backend {
+            LeaseBackend::File { path } => Store::File(path.clone()),
+            LeaseBackend::Grafana { instance } => Store::Grafana(Box::new(
+                instance
+                    .as_deref()
+                    .and_then(|name| instances.iter().find(|i| i.name() == name))
+                    .or_else(|| instances.first())
+                    .ok_or_else(|| {
+                        GSError::InvalidSetting(
+                            "leader_election".to_owned(),
+                            "the grafana backend needs an instance".to_owned(),
+                        )
+                    })?
+                    .clone(),
+            )),
+        };
+        let identity = settings.identity.clone().unwrap_or_else(|| {
+            let host = fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|h| h.trim().to_owned())
+                .unwrap_or_else(|_| "unknown".to_owned());
+            format!("{host}-{}", std::process::id())
+        });
+
+        Ok(Self {
+            store,
+            identity,
+            lease: Duration::from_secs(settings.lease_secs.max(3)),
+            leading_until: None,
+        })
+    }
+
+    /* Public API */
+
+    /// Takes the lease if it is free or expired, or renews it if this replica holds it.
+    /// Returns whether this replica leads now.
+    pub async fn elect(&mut self) -> bool {
+        let result = match &self.store {
+            Store::File(path) => self.elect_with_file(path).await,
+            Store::Grafana(instance) => self.elect_with_grafana(instance).await,
+        };
+
+        match result {
+            Ok(Some(lease)) => {
+                if self.leading_until.is_none() {
+                    info!("Leading the grafana-sync replicas as {}", self.identity);
+                }
+                self.leading_until = Some(lease.expires_at);
+            }
+            Ok(None) => {
+                if self.leading_until.take().is_some() {
+                    warn!("Lost the lead to another replica");
+                }
+            }
+            Err(e) => {
+                warn!("Could not take or renew the leader lease: {e}");
+                // the lease still holds until it expires, but the next renewal would come
+                // too late, so step down while no other replica can have taken over yet
+                let margin = self.renew_interval();
+                if self.leading_until.is_some_and(|until| until - margin <= Utc::now()) {
+                    warn!("Leader lease about to expire, standing by");
+                    self.leading_until = None;
+                }
+            }
+        }
+        self.leading_until.is_some()
+    }
+
+    /// Gives up the lease, so that a standby replica takes over right away.
+    pub async fn resign(&mut self) {
+        if self.leading_until.take().is_none() {
+            return;
+        }
+        let released = Lease {
+            holder: self.identity.clone(),
+            expires_at: Utc::now(),
+        };
+        let result = match &self.store {
+            Store::File(path) => Self::write_file(path, &released),
+            Store::Grafana(instance) => match instance.get_lease().await {
+                Ok(Some((Some(lease), version))) if lease.holder == self.identity => instance
+                    .put_lease(&released, Some(version))
+                    .await
+                    .map(|_| ()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            },
+        };
+        match result {
+            Ok(()) => info!("Released the leader lease"),
+            Err(e) => warn!("Could not release the leader lease, it expires on its own: {e}"),
+        }
+    }
+
+    /// How often the lease is renewed, and checked by standby replicas.
+    pub fn renew_interval(&self) -> Duration {
+        self.lease / 3
+    }
+
+    fn next_lease(&self) -> Lease {
+        Lease {
+            holder: self.identity.clone(),
+            expires_at: Utc::now() + self.lease,
+        }
+    }
+
+    fn is_free(&self, lease: &Lease) -> bool {
+        lease.holder == self.identity || lease.expires_at <= Utc::now()
+    }
+
+    /* Backends */
+
+    /// Best-effort only: two replicas taking over an expired lease at the same moment
+    /// both write the file, and the settle time lets the last write win. Shared storage
+    /// with a slow or caching view of the file (e.g. NFS) can still let both lead briefly.
+    async fn elect_with_file(&self, path: &Path) -> Result<Option<Lease>, GSError> {
+        let current = Self::read_file(path)?;
+        if current.as_ref().is_some_and(|lease| !self.is_free(lease)) {
+            return Ok(None);
+        }
+
+        let lease = self.next_lease();
+        Self::write_file(path, &lease)?;
+        if current.is_some_and(|lease| lease.holder == self.identity) {
+            return Ok(Some(lease));
+        }
+
+        // another replica may have taken over at the same time, the last write wins
+        tokio::time::sleep(FILE_SETTLE_TIME).await;
+        Ok(Self::read_file(path)?.filter(|l| *l == lease))
+    }
+
+    fn read_file(path: &Path) -> Result<Option<Lease>, GSError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        match serde_json::from_str::<Lease>(&fs::read_to_string(path)?) {
+            Ok(lease) => Ok(Some(lease)),
+            Err(e) => {
+                debug!(
+                    "Treating unreadable lease file {} as free: {e}",
+                    path.display()
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Replaces the file atomically, so that others never read half a lease.
+    fn write_file(path: &Path, lease: &Lease) -> Result<(), GSError> {
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, serde_json::to_vec(lease)?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    async fn elect_with_grafana(
+        &self,
+        instance: &GrafanaInstance,
+    ) -> Result<Option<Lease>, GSError> {
+        let version = match instance.get_lease().await? {
+            Some((Some(current), _)) if !self.is_free(&current) => return Ok(None),
+            Some((_, version)) => Some(version),
+            None => None,
+        };
+
+        let lease = self.next_lease();
+        // Grafana rejects the save if the dashboard was changed since it was read
+        match instance.put_lease(&lease, version).await? {
+            true => Ok(Some(lease)),
+            false => Ok(None),
+        }
+    }
+}
+
+/// Whether this replica currently leads, kept up to date in the background.
+#[derive(Debug)]
+pub struct Leadership {
+    leading: watch::Receiver<bool>,
+    // without election, keeps the channel open
+    _always: Option<watch::Sender<bool>>,
+    // stops the renewal, which then releases the lease
+    resign: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Leadership {
+
+    /* Constructors */
+
+    /// Leads unconditionally, for a single replica.
+    pub fn always() -> Self {
+        let (sender, leading) = watch::channel(true);
+        Self {
+            leading,
+            _always: Some(sender),
+            resign: None,
+            task: None,
+        }
+    }
+
+    /// Holds the first election right away, then keeps electing in the background
+    /// until [`Leadership::resign`].
+    pub async fn elect(mut elector: LeaderElector) -> Self {
+        let (sender, leading) = watch::channel(elector.elect().await);
+        let (resign, mut resigned) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut resigned => break,
+                    _ = tokio::time::sleep(elector.renew_interval()) => {}
+                }
+
+                let now_leading = elector.elect().await;
+                sender.send_if_modified(|leading| {
+                    std::mem::replace(leading, now_leading) != now_leading
+                });
+            }
+            sender.send_replace(false);
+            elector.resign().await;
+        });
+
+        Self {
+            leading,
+            _always: None,
+            resign: Some(resign),
+            task: Some(task),
+        }
+    }
+
+    /* Public API */
+
+    pub fn is_leading(&self) -> bool {
+        *self.leading.borrow()
+    }
+
+    /// Resolves once this replica leads.
+    pub async fn gained(&self) {
+        let mut leading = self.leading.clone();
+        let _ = leading.wait_for(|l| *l).await;
+    }
+
+    /// Resolves once this replica doesn't lead (anymore).
+    pub async fn lost(&self) {
+        let mut leading = self.leading.clone();
+        // the sender lives as long as the renewal does
+        let _ = leading.wait_for(|l| !*l).await;
+    }
+
+    /// Stops renewing and releases the lease.
+    pub async fn resign(&mut self) {
+        if let Some(resign) = self.resign.take() {
+            let _ = resign.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::api::library_elements::LibraryElement;
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+type SetName = String;
+
+/// A library panel which has to be written to some instances.
+#[derive(Debug, Clone)]
+pub struct LibraryUpdate<'a> {
+    pub element: &'a LibraryElement,
+    // (base_url, version of the outdated copy there, if any)
+    pub targets: Vec<(&'a str, Option<i64>)>,
+}
+
+/// Copies of the library panels used by synced dashboards, per instance.
+/// Models are expected in their shared datasource form, like the dashboards.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryState {
+    sets: HashMap<SetName, Vec<LibraryElement>>,
+}
+
+impl LibraryState {
+
+    /* Constructors */
+
+    pub fn add_set(&mut self, base_url: String, elements: Vec<LibraryElement>) {
+        self.sets.insert(base_url, elements);
+    }
+
+    /* public API */
+
+    /// Picks the newest copy of every library panel and lists the instances where it's
+    /// missing or different. Library panels are never deleted.
+    pub fn diff(&self) -> Vec<LibraryUpdate<'_>> {
+        let mut by_uid: HashMap<&str, Vec<(&str, &LibraryElement)>> = HashMap::new();
+        for (name, elements) in &self.sets {
+            for e in elements {
+                by_uid.entry(e.uid.as_str()).or_default().push((name.as_str(), e));
+            }
+        }
+
+        by_uid
+            .into_iter()
+            .filter_map(|(uid, copies)| {
+                let (_, newest) = copies.iter().copied().max_by_key(|(_, e)| e.meta.updated)?;
+
+                let targets = self
+                    .sets
+                    .keys()
+                    .filter_map(|name| {
+                        let copy = copies.iter().find(|(n, _)| n == name).map(|(_, e)| e);
+                        match copy {
+                            Some(e) if elements_equal(e, newest) => None,
+                            Some(e) => Some((name.as_str(), Some(e.version))),
+                            None => Some((name.as_str(), None)),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                debug!("library panel {uid}: {} outdated copies", targets.len());
+                (!targets.is_empty()).then_some(LibraryUpdate {
+                    element: newest,
+                    targets,
+                })
+            })
+            .collect()
+    }
+
+    pub fn unique_folders(&self) -> HashSet<&str> {
+        self.sets
+            .values()
+            .flat_map(|v| v.iter().map(|e| e.meta.folder_name.as_str()))
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+}
+
+#[inline]
+fn elements_equal(a: &LibraryElement, b: &LibraryElement) -> bool {
+    a.uid == b.uid
+        && a.name == b.name
+        && a.model == b.model
+        && a.meta.folder_name == b.meta.folder_name
+}
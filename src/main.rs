@@ -1,17 +1,35 @@
 use crate::config::Config;
 use crate::error::GSError;
+use crate::reload::ConfigWatcher;
 use crate::service::SyncService;
+use crate::shutdown::Shutdown;
 use log::{error, info, LevelFilter};
+use std::collections::BTreeSet;
 use std::env;
 use tracing::instrument;
 
+mod alert_state;
+mod annotation_state;
 pub mod api;
 mod config;
+mod dashboard_cache;
 mod dashboard_state;
+mod datasource_map;
+mod display_state;
 mod encrypted_cred;
 mod error;
 mod instance;
+mod leader;
+mod library_state;
+mod notification_state;
+mod oauth;
+mod reload;
+mod schedule;
+mod selector;
 mod service;
+mod shutdown;
+mod transform;
+mod trigger;
 
 #[tokio::main]
 async fn main() {
@@ -31,12 +49,31 @@ async fn run() -> Result<(), GSError> {
 
     let args: Vec<String> = env::args().collect();
     let config_path = args.get(1).map(|str| str.as_str()).unwrap_or("config.yaml");
+    // `grafana-sync <config> --sync [--force] <uid>...` syncs just these dashboards, then exits
+    let force = args.get(3).is_some_and(|arg| arg == "--force");
+    let first_uid = if force { 4 } else { 3 };
+    let sync_uids: Option<BTreeSet<String>> = match args.get(2).map(|arg| arg.as_str()) {
+        Some("--sync") if args.len() > first_uid => {
+            Some(args[first_uid..].iter().cloned().collect())
+        }
+        Some(arg) => {
+            let msg = format!("expected `--sync [--force] <uid>...`, got \"{arg}\"");
+            return Err(GSError::InvalidArguments(msg));
+        }
+        None => None,
+    };
 
     let config = Config::use_config_file(config_path)?;
 
     config.dbg_print();
 
-    SyncService::new(config).run().await?;
+    if let Some(uids) = &sync_uids {
+        return SyncService::new(config).sync_uids(uids, force).await;
+    }
+
+    let watcher = ConfigWatcher::new(config_path)?;
+    let shutdown = Shutdown::listen()?;
+    SyncService::new(config).run(watcher, shutdown).await?;
 
     Ok(())
 }
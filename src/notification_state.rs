@@ -0,0 +1,257 @@
+use crate::api::notifications::{ContactPoint, MuteTiming, NotificationTemplate};
+use crate::selector::NotificationSelector;
+use log::debug;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+type SetName = String;
+
+/// An instance's alerting notification settings. The policy tree is only fetched
+/// if routes are selected by label.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationSet {
+    pub contact_points: Vec<ContactPoint>,
+    pub templates: Vec<NotificationTemplate>,
+    pub mute_timings: Vec<MuteTiming>,
+    pub policies: Option<Value>,
+}
+
+/// What has to be written to one instance, in the order it has to be written in.
+#[derive(Debug, Clone)]
+pub struct NotificationUpdate<'a> {
+    pub target: &'a str,
+    pub templates: Vec<&'a NotificationTemplate>,
+    // (source copy, exists on the target)
+    pub mute_timings: Vec<(&'a MuteTiming, bool)>,
+    // (source copy, uid of the copy on the target)
+    pub contact_points: Vec<(&'a ContactPoint, Option<&'a str>)>,
+    pub policies: Option<Value>,
+}
+
+impl NotificationUpdate<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+            && self.mute_timings.is_empty()
+            && self.contact_points.is_empty()
+            && self.policies.is_none()
+    }
+}
+
+/// Notification settings of all instances. Unlike dashboards they carry no modification
+/// time, so one instance is the source and the others are brought in line with it.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationState {
+    sets: HashMap<SetName, NotificationSet>,
+}
+
+impl NotificationState {
+
+    /* Constructors */
+
+    pub fn add_set(&mut self, base_url: String, set: NotificationSet) {
+        self.sets.insert(base_url, set);
+    }
+
+    /* public API */
+
+    /// Lists what differs between the source and every other instance. Nothing is ever deleted.
+    ///
+    /// Secure settings come back redacted, so they can't be compared. `secrets_written` tells
+    /// whether the secrets configured for a contact point on a target were written already.
+    pub fn diff(
+        &self,
+        source: &str,
+        selector: &NotificationSelector,
+        secrets_written: impl Fn(&str, &ContactPoint) -> bool,
+    ) -> Vec<NotificationUpdate<'_>> {
+        let Some(source_set) = self.sets.get(source) else {
+            return vec![];
+        };
+
+        let routes = match (&source_set.policies, &selector.label) {
+            (Some(tree), Some(label)) => selected_routes(tree, label),
+            _ => vec![],
+        };
+        let mut receivers = HashSet::new();
+        let mut timings = HashSet::new();
+        routes
+            .iter()
+            .for_each(|r| collect_route_references(r, &mut receivers, &mut timings));
+
+        let templates = source_set
+            .templates
+            .iter()
+            .filter(|t| selector.matches_name(&t.name))
+            .collect::<Vec<_>>();
+        let mute_timings = source_set
+            .mute_timings
+            .iter()
+            .filter(|m| selector.matches_name(&m.name) || timings.contains(m.name.as_str()))
+            .collect::<Vec<_>>();
+        let contact_points = source_set
+            .contact_points
+            .iter()
+            .filter(|c| selector.matches_name(&c.name) || receivers.contains(c.name.as_str()))
+            .collect::<Vec<_>>();
+
+        self.sets
+            .iter()
+            .filter(|(name, _)| name.as_str() != source)
+            .map(|(name, set)| {
+                let update = NotificationUpdate {
+                    target: name.as_str(),
+                    templates: templates
+                        .iter()
+                        .copied()
+                        .filter(|t| !set.templates.contains(t))
+                        .collect(),
+                    mute_timings: mute_timings
+                        .iter()
+                        .copied()
+                        .filter_map(|m| match set.mute_timings.iter().find(|o| o.name == m.name) {
+                            Some(o) if o.time_intervals == m.time_intervals => None,
+                            other => Some((m, other.is_some())),
+                        })
+                        .collect(),
+                    contact_points: contact_points
+                        .iter()
+                        .copied()
+                        .filter_map(|c| {
+                            let copy = set
+                                .contact_points
+                                .iter()
+                                .find(|o| o.name == c.name && o.type_name == c.type_name);
+                            match copy {
+                                Some(o)
+                                    if contact_points_equal(o, c) && secrets_written(name, c) =>
+                                {
+                                    None
+                                }
+                                other => Some((c, other.map(|o| o.uid.as_str()))),
+                            }
+                        })
+                        .collect(),
+                    policies: match (&set.policies, &selector.label) {
+                        (Some(tree), Some(label)) => {
+                            let merged = merge_routes(tree, &routes, label);
+                            (merged != *tree).then_some(merged)
+                        }
+                        _ => None,
+                    },
+                };
+                debug!(
+                    "{name}: {} templates, {} mute timings, {} contact points outdated",
+                    update.templates.len(),
+                    update.mute_timings.len(),
+                    update.contact_points.len()
+                );
+                update
+            })
+            .filter(|u| !u.is_empty())
+            .collect()
+    }
+
+    pub fn print_data_stats(&self) {
+        for (name, set) in &self.sets {
+            debug!(
+                "{name}: {} contact points, {} templates, {} mute timings",
+                set.contact_points.len(),
+                set.templates.len(),
+                set.mute_timings.len()
+            );
+        }
+    }
+}
+
+#[inline]
+fn contact_points_equal(a: &ContactPoint, b: &ContactPoint) -> bool {
+    a.name == b.name
+        && a.type_name == b.type_name
+        && a.settings == b.settings
+        && a.disable_resolve_message == b.disable_resolve_message
+}
+
+/// The direct child routes of the root policy that match on `label`.
+fn selected_routes(tree: &Value, label: &str) -> Vec<Value> {
+    child_routes(tree)
+        .into_iter()
+        .flatten()
+        .filter(|r| matches_on_label(r, label))
+        .cloned()
+        .collect()
+}
+
+fn child_routes(tree: &Value) -> Option<&Vec<Value>> {
+    tree.get("routes")?.as_array()
+}
+
+fn matches_on_label(route: &Value, label: &str) -> bool {
+    let object_matchers = route
+        .get("object_matchers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m.get(0)?.as_str());
+    // legacy matchers are strings like `team=~"db.*"`
+    let matchers = route
+        .get("matchers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m.as_str())
+        .filter_map(|m| m.split(['=', '!']).next())
+        .map(str::trim);
+
+    object_matchers.chain(matchers).any(|name| name == label)
+}
+
+/// Receivers and time intervals a route and its children refer to.
+fn collect_route_references<'a>(
+    route: &'a Value,
+    receivers: &mut HashSet<&'a str>,
+    timings: &mut HashSet<&'a str>,
+) {
+    if let Some(receiver) = route.get("receiver").and_then(Value::as_str) {
+        receivers.insert(receiver);
+    }
+    for key in ["mute_time_intervals", "active_time_intervals"] {
+        timings.extend(
+            route
+                .get(key)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str),
+        );
+    }
+    for child in child_routes(route).into_iter().flatten() {
+        collect_route_references(child, receivers, timings);
+    }
+}
+
+/// Replaces the target's routes on `label` with the source's ones, at the position of the
+/// first one replaced. All other routes and the root policy stay as they are.
+fn merge_routes(target: &Value, routes: &[Value], label: &str) -> Value {
+    let mut merged = target.clone();
+    let Value::Object(root) = &mut merged else {
+        return merged;
+    };
+
+    let mut children = root
+        .get("routes")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let position = children
+        .iter()
+        .position(|r| matches_on_label(r, label))
+        .unwrap_or(children.len());
+    children.retain(|r| !matches_on_label(r, label));
+    let position = position.min(children.len());
+    children.splice(position..position, routes.iter().cloned());
+
+    if !children.is_empty() || root.contains_key("routes") {
+        root.insert("routes".into(), Value::Array(children));
+    }
+    merged
+}
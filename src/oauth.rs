@@ -0,0 +1,251 @@
+use crate::encrypted_cred::EncryptedCredential;
+use crate::error::GSError;
+use log::info;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+// Tokens are renewed this long before they expire, or after half their lifetime if shorter
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// Assumed lifetime of tokens whose response doesn't say
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Client credentials for an OAuth2 protected gateway in front of an instance.
+#[derive(Debug, Clone)]
+pub struct OAuth2Config {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: EncryptedCredential,
+    pub scopes: Vec<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// A token obtained with the client credentials flow.
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub value: EncryptedCredential,
+    pub expires_at: Instant,
+    // when it is renewed ahead of its expiry
+    pub refresh_at: Instant,
+}
+
+/// The token state of one instance. The lock makes sure that concurrent requests
+/// wait for a single refresh.
+#[derive(Debug)]
+pub struct OAuth2Session {
+    config: OAuth2Config,
+    // talks to the token endpoint, without any of the instance's headers
+    client: reqwest::Client,
+    // expiry and renewal time of the token in use
+    current: Mutex<Option<(Instant, Instant)>>,
+}
+
+impl OAuth2Session {
+    pub fn new(config: OAuth2Config, client: reqwest::Client) -> Self {
+        Self {
+            config,
+            client,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Expiry of the token in use, which identifies it.
+    pub async fn current(&self) -> Option<Instant> {
+        self.current.lock().await.map(|(expires_at, _)| expires_at)
+    }
+
+    /// Obtains a new token if there is none, the current one expires soon, or `rejected`
+    /// is the token in use. `install` puts a new token to use.
+    /// Returns whether a new token was obtained.
+    pub async fn refresh(
+        &self,
+        rejected: Option<Option<Instant>>,
+        install: impl FnOnce(&AccessToken) -> Result<(), GSError>,
+    ) -> Result<bool, GSError> {
+        let mut current = self.current.lock().await;
+        let needed = match rejected {
+            Some(rejected) => rejected == current.map(|(expires_at, _)| expires_at),
+            None => current.is_none_or(|(_, refresh_at)| refresh_at <= Instant::now()),
+        };
+        if !needed {
+            return Ok(false);
+        }
+
+        let token = self.request_token().await?;
+        install(&token)?;
+        *current = Some((token.expires_at, token.refresh_at));
+        Ok(true)
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, GSError> {
+        let mut form = vec![("grant_type", "client_credentials".to_owned())];
+        if !self.config.scopes.is_empty() {
+            form.push(("scope", self.config.scopes.join(" ")));
+        }
+        if let Some(audience) = &self.config.audience {
+            form.push(("audience", audience.clone()));
+        }
+
+        let requested_at = Instant::now();
+        let response = self
+            .client
+            .post(&self.config.token_url)
+            .basic_auth(&self.config.client_id, Some(self.config.client_secret.value()))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?;
+        let token = serde_json::from_str::<TokenResponse>(&response.text().await?)?;
+
+        let lifetime = token
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LIFETIME);
+        info!(
+            "Obtained OAuth2 token from {}, valid for {}s",
+            self.config.token_url,
+            lifetime.as_secs()
+        );
+
+        // a fixed margin would renew short-lived tokens before every request
+        let margin = REFRESH_MARGIN.min(lifetime / 2);
+        Ok(AccessToken {
+            value: token.access_token.into(),
+            expires_at: requested_at + lifetime,
+            refresh_at: requested_at + lifetime - margin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{Auth, GrafanaInstance, InstanceOptions};
+    use futures::future::join_all;
+    use http_body_util::Full;
+    use hyper::body::{Bytes, Incoming};
+    use hyper::header::AUTHORIZATION;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// Hands out `token-1`, `token-2`, ... valid for `expires_in` seconds at `/token`, and
+    /// answers `/api/health` with 401 for `token-1`, as if it had been revoked.
+    /// Returns the base url and how many tokens were handed out.
+    async fn stand_in(expires_in: u64) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let issued = Arc::new(AtomicUsize::new(0));
+
+        let counter = issued.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let counter = counter.clone();
+                let service = service_fn(move |request: Request<Incoming>| {
+                    let counter = counter.clone();
+                    async move {
+                        let (status, body) = match request.uri().path() {
+                            "/token" => {
+                                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                                let body = serde_json::json!({
+                                    "access_token": format!("token-{n}"),
+                                    "expires_in": expires_in,
+                                });
+                                (StatusCode::OK, body.to_string())
+                            }
+                            _ => match request.headers().get(AUTHORIZATION) {
+                                Some(value) if value == "Bearer token-1" => {
+                                    (StatusCode::UNAUTHORIZED, "{}".to_owned())
+                                }
+                                Some(_) => (StatusCode::OK, "{}".to_owned()),
+                                None => (StatusCode::UNAUTHORIZED, "{}".to_owned()),
+                            },
+                        };
+                        let response = Response::builder()
+                            .status(status)
+                            .body(Full::new(Bytes::from(body)))
+                            .unwrap();
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (url, issued)
+    }
+
+    fn config(url: &str) -> OAuth2Config {
+        OAuth2Config {
+            token_url: format!("{url}/token"),
+            client_id: "grafana-sync".to_owned(),
+            client_secret: "secret".to_owned().into(),
+            scopes: vec![],
+            audience: None,
+        }
+    }
+
+    async fn refresh(session: &OAuth2Session, rejected: Option<Option<Instant>>) -> bool {
+        session.refresh(rejected, |_| Ok(())).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetches_a_token_once_while_it_is_fresh() {
+        let (url, issued) = stand_in(30).await;
+        let session = OAuth2Session::new(config(&url), reqwest::Client::new());
+
+        assert!(refresh(&session, None).await);
+        assert!(session.current().await.is_some());
+        // a lifetime below the fixed margin mustn't renew the token on every request
+        assert!(!refresh(&session, None).await);
+        assert!(!refresh(&session, None).await);
+        assert_eq!(issued.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn renews_the_token_before_it_expires() {
+        let (url, issued) = stand_in(2).await;
+        let session = OAuth2Session::new(config(&url), reqwest::Client::new());
+
+        assert!(refresh(&session, None).await);
+        let first = session.current().await;
+        assert!(!refresh(&session, None).await);
+
+        // renewed after half of the 2s lifetime, before the token expires
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(first.is_some_and(|expires_at| Instant::now() < expires_at));
+        assert!(refresh(&session, None).await);
+        assert_ne!(session.current().await, first);
+        assert_eq!(issued.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_rejections_renew_the_token_once() {
+        let (url, issued) = stand_in(300).await;
+        let auth = Auth::OAuth2(config(&url));
+        let instance = GrafanaInstance::new(url.clone(), auth, InstanceOptions::default()).unwrap();
+
+        let requests = (0..8).map(|_| {
+            let request = instance.client().get(format!("{url}/api/health"));
+            instance.send(request)
+        });
+        for response in join_all(requests).await {
+            assert_eq!(response.unwrap().status(), StatusCode::OK);
+        }
+        // the first token, and a single renewal after all requests got 401 with it
+        assert_eq!(issued.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::config::Config;
+use crate::error::GSError;
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+// How often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tells when the config should be read again: on SIGHUP, or when the file was modified.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    hangup: Signal,
+}
+
+impl ConfigWatcher {
+
+    /* Constructors */
+
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, GSError> {
+        let path = path.into();
+        Ok(Self {
+            modified: Self::modified(&path),
+            path,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    /* Public API */
+
+    /// Resolves once the config should be read again.
+    pub async fn changed(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.hangup.recv() => {
+                    info!("Received SIGHUP, reloading {}", self.path.display());
+                    return;
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {
+                    if Self::modified(&self.path) != self.modified {
+                        info!("{} changed, reloading it", self.path.display());
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads and validates the config again. An invalid config is logged and `None` returned,
+    /// so that the caller keeps the one it has.
+    pub fn reload(&mut self) -> Option<Config> {
+        // remembered up front, so that a broken file is only reported once per change
+        self.modified = Self::modified(&self.path);
+        if self.modified.is_none() {
+            warn!(
+                "{} is gone, keeping the current config",
+                self.path.display()
+            );
+            return None;
+        }
+
+        match Config::use_config_file(&self.path) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!(
+                    "Invalid config in {}, keeping the current one: {e}",
+                    self.path.display()
+                );
+                None
+            }
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::GSError;
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveTime, TimeDelta, Weekday};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Longest duration accepted in the config, anything longer is most likely a typo.
+pub const MAX_DURATION: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+// How far ahead a trigger which never fires again is put
+const NEVER: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// When cycles are due.
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl PartialEq for Trigger {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Trigger::Every(a), Trigger::Every(b)) => a == b,
+            (Trigger::Cron(a), Trigger::Cron(b)) => a.source() == b.source(),
+            _ => false,
+        }
+    }
+}
+
+impl Trigger {
+    /// Accepts standard five-field crontab lines as well as the six or seven field
+    /// format with seconds (and years).
+    pub fn cron(expression: &str) -> Result<Self, GSError> {
+        let expression = match expression.split_whitespace().count() {
+            5 => format!("0 {expression}"),
+            _ => expression.to_owned(),
+        };
+        cron::Schedule::from_str(&expression)
+            .map(|s| Trigger::Cron(Box::new(s)))
+            .map_err(|e| GSError::InvalidSchedule(format!("cron \"{expression}\": {e}")))
+    }
+
+    /// The first time the trigger fires after `after`.
+    fn after(&self, after: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Trigger::Every(period) => TimeDelta::from_std(*period)
+                .ok()
+                .and_then(|period| after.checked_add_signed(period))
+                .unwrap_or(after + NEVER),
+            // a schedule without any future date never fires again
+            Trigger::Cron(schedule) => schedule.after(&after).next().unwrap_or(after + NEVER),
+        }
+    }
+}
+
+/// What happens to ticks which passed while a cycle was still running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedTicks {
+    /// Catch up on all of them, back-to-back.
+    #[default]
+    Burst,
+    /// Wait a full period after the late cycle; like `Skip` for cron schedules.
+    Delay,
+    /// Drop them and wait for the next tick on schedule.
+    Skip,
+}
+
+/// A recurring period, in local time, during which cycles may run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncWindow {
+    // every day if empty
+    pub days: Vec<Weekday>,
+    pub from: NaiveTime,
+    // before `from` for windows across midnight
+    pub to: NaiveTime,
+}
+
+impl SyncWindow {
+    fn contains(&self, at: DateTime<Local>) -> bool {
+        if !self.days.is_empty() && !self.days.contains(&at.weekday()) {
+            return false;
+        }
+        let time = at.time();
+        match self.from <= self.to {
+            true => self.from <= time && time < self.to,
+            false => self.from <= time || time < self.to,
+        }
+    }
+}
+
+/// A period, e.g. a change freeze, during which no cycles run.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Blackout {
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+}
+
+impl Blackout {
+    pub fn contains(&self, at: DateTime<Local>) -> bool {
+        self.from <= at && at < self.to
+    }
+}
+
+/// When sync cycles run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub trigger: Trigger,
+    // each tick is delayed by up to this much
+    pub jitter: Duration,
+    pub missed_ticks: MissedTicks,
+    // cycles only run within one of them, if any are set
+    pub windows: Vec<SyncWindow>,
+    pub blackouts: Vec<Blackout>,
+}
+
+impl Schedule {
+
+    /* Public API */
+
+    /// Why no cycle may run at `at`, if it may not.
+    pub fn closed_reason(&self, at: DateTime<Local>) -> Option<String> {
+        if let Some(blackout) = self.blackouts.iter().find(|b| b.contains(at)) {
+            return Some(format!("blackout until {}", blackout.to));
+        }
+        if !self.windows.is_empty() && !self.windows.iter().any(|w| w.contains(at)) {
+            return Some("outside of the sync windows".to_owned());
+        }
+        None
+    }
+
+    /// The usual time between two cycles around `at`.
+    pub fn nominal_interval(&self, at: DateTime<Local>) -> Duration {
+        let next = self.trigger.after(at);
+        (self.trigger.after(next) - next)
+            .to_std()
+            .unwrap_or_default()
+    }
+}
+
+/// Parses durations like `90s`, `15m`, `1h30m` or `2d`. A plain number counts seconds.
+/// Durations above [`MAX_DURATION`] are rejected.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<u64>() {
+        return Some(Duration::from_secs(secs)).filter(|d| *d <= MAX_DURATION);
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    Some(Duration::from_secs(total)).filter(|d| number.is_empty() && *d <= MAX_DURATION)
+}
+
+/// Waits for the ticks of a [`Schedule`].
+#[derive(Debug)]
+pub struct Ticker {
+    schedule: Schedule,
+    // scheduled time of the last tick, `None` before the first
+    last: Option<DateTime<Local>>,
+    // scheduled time of the next tick, and when it's due with jitter
+    pending: Option<(DateTime<Local>, DateTime<Local>)>,
+}
+
+impl Ticker {
+
+    /* Constructors */
+
+    /// The first tick is due right away.
+    pub fn new(schedule: Schedule) -> Self {
+        let now = Local::now();
+        Self {
+            schedule,
+            last: None,
+            pending: Some((now, now)),
+        }
+    }
+
+    /* Public API */
+
+    /// Waits for the next tick. Cancel safe: the tick stays due.
+    pub async fn tick(&mut self) {
+        let (scheduled, due) = *self.pending.get_or_insert_with(|| {
+            let scheduled = Self::next(&self.schedule, self.last);
+            let jitter = fastrand::u64(0..=self.schedule.jitter.as_millis() as u64);
+            (scheduled, scheduled + Duration::from_millis(jitter))
+        });
+
+        let wait = (due - Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        self.last = Some(scheduled);
+        self.pending = None;
+    }
+
+    /// Makes the next tick due right away.
+    pub fn reset_immediately(&mut self) {
+        let now = Local::now();
+        self.pending = Some((now, now));
+    }
+
+    /// Continues with another schedule, counting from now.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+        self.last = Some(Local::now());
+        self.pending = None;
+    }
+
+    fn next(schedule: &Schedule, last: Option<DateTime<Local>>) -> DateTime<Local> {
+        let now = Local::now();
+        let Some(last) = last else {
+            return now;
+        };
+        match (schedule.missed_ticks, &schedule.trigger) {
+            (MissedTicks::Burst, trigger) => trigger.after(last),
+            (MissedTicks::Delay, trigger @ Trigger::Every(_)) => match trigger.after(last) < now {
+                true => trigger.after(now),
+                false => trigger.after(last),
+            },
+            (_, trigger) => {
+                let mut next = trigger.after(last);
+                while next < now {
+                    next = trigger.after(next);
+                }
+                next
+            }
+        }
+    }
+}
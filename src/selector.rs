@@ -0,0 +1,386 @@
+use crate::api::dashboards::SimpleDashboard;
+use crate::api::leases::LEASE_DASHBOARD_UID;
+use crate::error::GSError;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/* Config representation */
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+/// One rule as written in the config. All criteria that are set must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleConfig {
+    pub tags: Vec<String>,
+    pub tags_match: TagMatch,
+    pub folders: Vec<String>,
+    pub titles: Vec<String>,
+    pub uids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncGroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub include: Vec<RuleConfig>,
+    #[serde(default)]
+    pub exclude: Vec<RuleConfig>,
+}
+
+/* Compiled representation */
+
+#[derive(Debug, Clone)]
+struct Rule {
+    tags: Vec<String>,
+    tags_match: TagMatch,
+    folders: Option<GlobSet>,
+    titles: Option<RegexSet>,
+    uids: HashSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncGroup {
+    name: String,
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+/// Decides which dashboards take part in the sync. A dashboard is selected if any
+/// group includes it, the same group doesn't exclude it and no global exclude rule matches.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    groups: Vec<SyncGroup>,
+    exclude: Vec<Rule>,
+}
+
+impl Rule {
+    fn compile(group: &str, config: &RuleConfig) -> Result<Self, GSError> {
+        let invalid =
+            |e: &dyn std::fmt::Display| GSError::InvalidSelector(group.to_owned(), e.to_string());
+
+        let folders = if config.folders.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            // `*` stays within one folder, `**` is needed to match nested folders too
+            for pattern in &config.folders {
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| invalid(&e))?;
+                builder.add(glob);
+            }
+            Some(builder.build().map_err(|e| invalid(&e))?)
+        };
+
+        let titles = if config.titles.is_empty() {
+            None
+        } else {
+            Some(RegexSet::new(&config.titles).map_err(|e| invalid(&e))?)
+        };
+
+        Ok(Rule {
+            tags: config.tags.clone(),
+            tags_match: config.tags_match,
+            folders,
+            titles,
+            uids: config.uids.iter().cloned().collect(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+            && self.folders.is_none()
+            && self.titles.is_none()
+            && self.uids.is_empty()
+    }
+
+    /// `folder_path` is the full path of the dashboard's folder, e.g. `Team/Sub`.
+    fn matches(&self, dashboard: &SimpleDashboard, folder_path: &str) -> bool {
+        let tags_ok = self.tags.is_empty()
+            || match self.tags_match {
+                TagMatch::Any => self.tags.iter().any(|t| dashboard.tags.contains(t)),
+                TagMatch::All => self.tags.iter().all(|t| dashboard.tags.contains(t)),
+            };
+        let folder_ok = self.folders.as_ref().is_none_or(|g| g.is_match(folder_path));
+        let title_ok = self
+            .titles
+            .as_ref()
+            .is_none_or(|r| r.is_match(&dashboard.title));
+        let uid_ok = self.uids.is_empty() || self.uids.contains(&dashboard.uid);
+
+        tags_ok && folder_ok && title_ok && uid_ok
+    }
+}
+
+impl SyncGroup {
+    pub fn compile(config: &SyncGroupConfig) -> Result<Self, GSError> {
+        let compile_all = |rules: &[RuleConfig]| {
+            rules
+                .iter()
+                .map(|r| Rule::compile(&config.name, r))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let group = SyncGroup {
+            name: config.name.clone(),
+            include: compile_all(&config.include)?,
+            exclude: compile_all(&config.exclude)?,
+        };
+
+        if group.include.is_empty() || group.include.iter().any(Rule::is_empty) {
+            return Err(GSError::InvalidSelector(
+                group.name,
+                "every include rule needs at least one criterion".to_owned(),
+            ));
+        }
+        Ok(group)
+    }
+
+    /// The group the plain `sync_tag` setting stands for.
+    pub fn from_tag(tag: &str) -> Self {
+        SyncGroup {
+            name: format!("tag:{tag}"),
+            include: vec![Rule {
+                tags: vec![tag.to_owned()],
+                tags_match: TagMatch::Any,
+                folders: None,
+                titles: None,
+                uids: HashSet::new(),
+            }],
+            exclude: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, dashboard: &SimpleDashboard, folder_path: &str) -> bool {
+        self.include.iter().any(|r| r.matches(dashboard, folder_path))
+            && !self.exclude.iter().any(|r| r.matches(dashboard, folder_path))
+    }
+}
+
+impl Selector {
+    /// `exclude` applies across all groups, including the one of the plain `sync_tag`.
+    pub fn new(groups: Vec<SyncGroup>, exclude: &[RuleConfig]) -> Result<Self, GSError> {
+        let exclude = exclude
+            .iter()
+            .map(|r| Rule::compile("sync_exclude", r))
+            .collect::<Result<Vec<_>, _>>()?;
+        if exclude.iter().any(Rule::is_empty) {
+            return Err(GSError::InvalidSelector(
+                "sync_exclude".to_owned(),
+                "every rule needs at least one criterion".to_owned(),
+            ));
+        }
+        Ok(Self { groups, exclude })
+    }
+
+    pub fn groups(&self) -> &[SyncGroup] {
+        &self.groups
+    }
+
+    /// `folder_path` is the full path of the dashboard's folder as given by
+    /// [`FolderPaths::path_of`](crate::api::folders::FolderPaths::path_of).
+    /// The lease dashboard of the leader election is never selected.
+    pub fn matches(&self, dashboard: &SimpleDashboard, folder_path: &str) -> bool {
+        dashboard.uid != LEASE_DASHBOARD_UID
+            && self.groups.iter().any(|g| g.matches(dashboard, folder_path))
+            && !self.exclude.iter().any(|r| r.matches(dashboard, folder_path))
+    }
+
+    /// Tags whose dashboard count can be used to double check a listing for completeness.
+    pub fn included_tags(&self) -> HashSet<&str> {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.include)
+            .flat_map(|r| &r.tags)
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Chooses the alert rules to sync by one of their labels.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRuleSelector {
+    pub label: String,
+    // any value matches if unset
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+impl AlertRuleSelector {
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        labels
+            .get(&self.label)
+            .is_some_and(|v| self.value.as_ref().is_none_or(|expected| expected == v))
+    }
+}
+
+/// Chooses the notification settings to sync. Contact points, templates and mute timings are
+/// selected by name prefix; child routes of the notification policy tree by a label they match on.
+/// Contact points and mute timings which selected routes use are synced along with them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationSelector {
+    // base url of the instance whose settings are copied, the first instance if unset
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl NotificationSelector {
+    pub fn validate(&self) -> Result<(), GSError> {
+        if self.prefix.as_deref().is_none_or(str::is_empty) && self.label.is_none() {
+            return Err(GSError::InvalidSetting(
+                "notifications".to_owned(),
+                "either prefix or label is needed".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.prefix
+            .as_deref()
+            .is_some_and(|p| !p.is_empty() && name.starts_with(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dashboard(uid: &str, title: &str, tags: &[&str]) -> SimpleDashboard {
+        SimpleDashboard {
+            id: 1,
+            uid: uid.to_owned(),
+            title: title.to_owned(),
+            uri: String::new(),
+            url: String::new(),
+            slug: String::new(),
+            type_name: "dash-db".to_owned(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            is_starred: false,
+            folder_id: None,
+            folder_uid: None,
+            folder_title: None,
+            folder_url: None,
+            sort_meta: 0,
+            version: None,
+            updated: None,
+        }
+    }
+
+    fn selector(groups: &str, exclude: &str) -> Selector {
+        let groups = serde_yaml::from_str::<Vec<SyncGroupConfig>>(groups)
+            .unwrap()
+            .iter()
+            .map(SyncGroup::compile)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let exclude = serde_yaml::from_str::<Vec<RuleConfig>>(exclude).unwrap();
+        Selector::new(groups, &exclude).unwrap()
+    }
+
+    #[test]
+    fn matches_any_or_all_tags() {
+        let any = selector("[{name: g, include: [{tags: [a, b]}]}]", "[]");
+        let all = selector("[{name: g, include: [{tags: [a, b], tags_match: all}]}]", "[]");
+
+        assert!(any.matches(&dashboard("x", "X", &["b"]), "General"));
+        assert!(!any.matches(&dashboard("x", "X", &["c"]), "General"));
+        assert!(!all.matches(&dashboard("x", "X", &["b"]), "General"));
+        assert!(all.matches(&dashboard("x", "X", &["a", "b", "c"]), "General"));
+    }
+
+    #[test]
+    fn folder_globs_need_double_stars_for_nested_folders() {
+        let single = selector("[{name: g, include: [{folders: [\"Team/*\"]}]}]", "[]");
+        let nested = selector("[{name: g, include: [{folders: [\"Team/**\"]}]}]", "[]");
+        let d = dashboard("x", "X", &[]);
+
+        assert!(single.matches(&d, "Team/Sub"));
+        assert!(!single.matches(&d, "Team/Sub/Deep"));
+        assert!(!single.matches(&d, "Team"));
+        assert!(nested.matches(&d, "Team/Sub"));
+        assert!(nested.matches(&d, "Team/Sub/Deep"));
+        assert!(!nested.matches(&d, "Other/Team/Sub"));
+    }
+
+    #[test]
+    fn matches_titles_by_regex() {
+        let s = selector("[{name: g, include: [{titles: [\"^BGP \"]}]}]", "[]");
+
+        assert!(s.matches(&dashboard("x", "BGP peers", &[]), "General"));
+        assert!(!s.matches(&dashboard("x", "Overview of BGP ", &[]), "General"));
+    }
+
+    #[test]
+    fn criteria_of_a_rule_all_need_to_match() {
+        let s = selector("[{name: g, include: [{tags: [a], folders: [Net]}]}]", "[]");
+
+        assert!(s.matches(&dashboard("x", "X", &["a"]), "Net"));
+        assert!(!s.matches(&dashboard("x", "X", &["a"]), "General"));
+        assert!(!s.matches(&dashboard("x", "X", &[]), "Net"));
+    }
+
+    #[test]
+    fn group_excludes_only_apply_to_their_group() {
+        let s = selector(
+            "[
+                {name: a, include: [{tags: [a]}], exclude: [{tags: [draft]}]},
+                {name: b, include: [{tags: [b]}]},
+            ]",
+            "[]",
+        );
+
+        assert!(!s.matches(&dashboard("x", "X", &["a", "draft"]), "General"));
+        assert!(s.matches(&dashboard("x", "X", &["a", "b", "draft"]), "General"));
+    }
+
+    #[test]
+    fn global_excludes_apply_to_all_groups() {
+        let mut s = selector(
+            "[{name: a, include: [{tags: [a]}]}, {name: b, include: [{tags: [b]}]}]",
+            "[{folders: [\"Team/Drafts/**\"]}, {uids: [secret]}]",
+        );
+        s.groups.push(SyncGroup::from_tag("SyncMe"));
+
+        assert!(s.matches(&dashboard("x", "X", &["b"]), "Team/Drafts"));
+        assert!(!s.matches(&dashboard("x", "X", &["b"]), "Team/Drafts/Old"));
+        assert!(!s.matches(&dashboard("x", "X", &["a", "b"]), "Team/Drafts/Old"));
+        assert!(!s.matches(&dashboard("secret", "X", &["SyncMe"]), "General"));
+        assert!(s.matches(&dashboard("x", "X", &["SyncMe"]), "General"));
+    }
+
+    #[test]
+    fn never_selects_the_lease_dashboard() {
+        let s = selector("[{name: g, include: [{titles: [\".*\"]}]}]", "[]");
+
+        assert!(s.matches(&dashboard("x", "Lease", &[]), "General"));
+        assert!(!s.matches(&dashboard(LEASE_DASHBOARD_UID, "Lease", &[]), "General"));
+    }
+
+    #[test]
+    fn rejects_rules_without_criteria() {
+        let groups = serde_yaml::from_str::<Vec<SyncGroupConfig>>("[{name: g, include: [{}]}]");
+        assert!(SyncGroup::compile(&groups.unwrap()[0]).is_err());
+        assert!(Selector::new(vec![], &[RuleConfig::default()]).is_err());
+    }
+}
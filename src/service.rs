@@ -1,26 +1,66 @@
-use crate::api::dashboards::{Folder, FullDashboard};
-use crate::config::Config;
+use crate::alert_state::{AlertGroupCopy, AlertState};
+use crate::api::dashboards::FullDashboard;
+use crate::api::folders::FolderIndex;
+use crate::api::notifications::ContactPoint;
+use crate::annotation_state::{AnnotationChange, AnnotationState};
+use crate::api::annotations::ANNOTATION_LIMIT;
+use crate::config::{
+    AnnotationSettings, Config, DisplaySettings, MissingDependencyPolicy, UntagPolicy,
+};
+use crate::dashboard_cache::DashboardCache;
 use crate::dashboard_state::DashboardState;
+use crate::datasource_map::DatasourceMap;
+use crate::display_state::{DisplaySet, DisplayState, DisplayUpdate};
+use crate::encrypted_cred::EncryptedCredential;
 use crate::error::GSError;
-use crate::instance::GrafanaInstance;
+use crate::instance::{GrafanaInstance, OrgSelection};
+use crate::leader::{LeaderElector, Leadership};
+use crate::library_state::LibraryState;
+use crate::reload::ConfigWatcher;
+use crate::notification_state::{NotificationSet, NotificationState, NotificationUpdate};
+use crate::shutdown::Shutdown;
+use crate::schedule::Ticker;
+use crate::trigger::{self, TriggerQueue, TriggerScope};
+use crate::selector::{AlertRuleSelector, NotificationSelector, Selector};
+use crate::transform::Pipeline;
 use chrono::Local;
 use futures::StreamExt;
 use futures::stream::FuturesUnordered;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use tokio::time::Instant;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::sync::RwLock;
 use tracing::instrument;
 
-// base_url -> (folder title -> Folder)
-pub type FolderMap = HashMap<String, HashMap<String, Folder>>;
+// base_url -> folders on that instance
+pub type FolderMap = HashMap<String, FolderIndex>;
+
+/// What is known about an instance for the current cycle, used to prepare and check
+/// the dashboards imported onto it.
+#[derive(Debug, Clone, Default)]
+struct TargetContext {
+    datasources: DatasourceMap,
+    // ids of the installed panel plugins, if they're checked
+    panel_plugins: Option<HashSet<String>>,
+}
+
+// instance name -> context of that instance
+type TargetContexts = HashMap<String, TargetContext>;
+
+// (instance name, selected dashboards, uids of unselected dashboards)
+type InstanceSet = (String, Vec<FullDashboard>, HashSet<String>);
 
 /// Periodically synchronises all tagged dashboards across *all* instances.
 #[derive(Debug, Clone)]
 pub struct SyncService {
     cfg: Arc<Config>,
+    cache: Arc<RwLock<DashboardCache>>,
+    // shared name of the org synced, if instances were expanded to all their orgs
+    org: Option<String>,
 }
 
 impl SyncService {
@@ -29,97 +69,1217 @@ impl SyncService {
 
     #[instrument(skip_all)]
     pub fn new(cfg: Config) -> Self {
-        Self { cfg: Arc::new(cfg) }
+        let cache = DashboardCache::new(cfg.cache_file.clone(), cfg.state_file.clone());
+        Self {
+            cfg: Arc::new(cfg),
+            cache: Arc::new(RwLock::new(cache)),
+            org: None,
+        }
+    }
+
+    /// Expands instances that stand for all orgs of their server into one instance per org,
+    /// and splits the instances into one service per shared org name, each with its own cache.
+    /// All other instances keep syncing with each other in this service.
+    /// Orgs which already had a service in `previous` keep its cache.
+    async fn split_by_org(&self, previous: &[SyncService]) -> Result<Vec<SyncService>, GSError> {
+        if !self
+            .cfg
+            .instances
+            .iter()
+            .any(|i| i.options().org == OrgSelection::All)
+        {
+            return Ok(vec![self.clone()]);
+        }
+
+        let mut by_org: BTreeMap<Option<String>, Vec<GrafanaInstance>> = BTreeMap::new();
+        for instance in &self.cfg.instances {
+            if instance.options().org != OrgSelection::All {
+                by_org.entry(None).or_default().push(instance.clone());
+                continue;
+            }
+            for org in instance.get_orgs().await? {
+                let expanded = instance.with_org(org.id, &org.name)?;
+                by_org
+                    .entry(expanded.shared_org().map(str::to_owned))
+                    .or_default()
+                    .push(expanded);
+            }
+        }
+
+        let mut services = Vec::new();
+        for (org, instances) in by_org {
+            let names = instances.iter().map(|i| i.name()).collect::<Vec<_>>();
+            if instances.len() < 2 {
+                info!("Nothing to sync with for {}", names.join(", "));
+                continue;
+            }
+            info!(
+                "Syncing org {:?} between {}",
+                org.as_deref().unwrap_or("-"),
+                names.join(", ")
+            );
+
+            let mut cfg = (*self.cfg).clone();
+            cfg.instances = instances;
+            let service = match &org {
+                None => SyncService {
+                    cfg: Arc::new(cfg),
+                    cache: self.cache.clone(),
+                    org: None,
+                },
+                Some(name) => match previous.iter().find(|s| s.org.as_ref() == Some(name)) {
+                    Some(existing) => SyncService {
+                        cfg: Arc::new(cfg),
+                        cache: existing.cache.clone(),
+                        org: Some(name.clone()),
+                    },
+                    None => {
+                        cfg.cache_file = cfg.cache_file.map(|p| org_cache_file(&p, name));
+                        cfg.state_file = org_cache_file(&cfg.state_file, name);
+                        SyncService {
+                            org: Some(name.clone()),
+                            ..SyncService::new(cfg)
+                        }
+                    }
+                },
+            };
+            services.push(service);
+        }
+        Ok(services)
     }
 
     /* Public API */
 
-    /// Runs forever, every sync_cycle_interval
+    /// Syncs the dashboards with the given UIDs once, on all orgs. Like a first cycle,
+    /// this never propagates deletions.
+    /// Unless `force`d, refuses outside of the schedule's windows, within a blackout and
+    /// with leader election, where the running replicas are in charge.
+    #[instrument(skip_all)]
+    pub async fn sync_uids(&self, uids: &BTreeSet<String>, force: bool) -> Result<(), GSError> {
+        if !force {
+            if let Some(reason) = self.cfg.schedule.closed_reason(Local::now()) {
+                let msg = format!("no cycle may run now, {reason}; add --force to sync anyway");
+                return Err(GSError::InvalidArguments(msg));
+            }
+            if self.cfg.leader_election.is_some() {
+                let msg = "leader_election is configured, so the leading replica syncs; \
+                    add --force to sync anyway"
+                    .to_owned();
+                return Err(GSError::InvalidArguments(msg));
+            }
+        }
+
+        let interval = self.cfg.schedule.nominal_interval(Local::now());
+        let interval_mins = interval.as_secs().div_ceil(60).max(1);
+        for service in self.split_by_org(&[]).await? {
+            if let Some(org) = &service.org {
+                info!("--- org \"{org}\" ---");
+            }
+            service.run_partial_cycle(0, interval_mins, uids).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs every sync_cycle_interval until `shutdown` is requested. Between cycles, the config
+    /// is replaced whenever `watcher` reports a valid change.
     #[instrument(skip_all)]
-    pub async fn run(&self) -> Result<(), GSError> {
-        let mut tick = tokio::time::interval(Duration::from_secs(self.cfg.sync_rate_mins * 60));
+    pub async fn run(
+        &mut self,
+        mut watcher: ConfigWatcher,
+        shutdown: Shutdown,
+    ) -> Result<(), GSError> {
+        let mut services = self.split_by_org(&[]).await?;
+        let mut leadership = match &self.cfg.leader_election {
+            Some(settings) => {
+                Leadership::elect(LeaderElector::new(settings, &self.cfg.instances)?).await
+            }
+            None => Leadership::always(),
+        };
+        let triggers = TriggerQueue::default();
+        if let Some(settings) = &self.cfg.trigger {
+            let listener = TcpListener::bind(settings.listen).await?;
+            info!("Listening for triggers on http://{}/trigger", settings.listen);
+            tokio::spawn(trigger::serve(listener, settings.clone(), triggers.clone()));
+        }
+        let debounce = self.cfg.trigger.as_ref().map(|t| t.debounce).unwrap_or_default();
+        let mut ticker = Ticker::new(self.cfg.schedule.clone());
         let mut cycle = 0usize;
+        let mut last_cycle: Option<Instant> = None;
 
         loop {
-            tick.tick().await;
-            
-            info!("=== sync-cycle #{cycle} ({}) ===", Local::now());
+            let scope = tokio::select! {
+                biased;
+                _ = shutdown.requested() => break,
+                _ = ticker.tick() => TriggerScope::All,
+                scope = triggers.next(debounce), if self.cfg.trigger.is_some() => scope,
+                _ = leadership.gained(), if !leadership.is_leading() => {
+                    // the previous leader's schedule is unknown, so sync right away
+                    ticker.reset_immediately();
+                    continue;
+                }
+                _ = watcher.changed() => {
+                    let Some(cfg) = watcher.reload() else {
+                        continue;
+                    };
+                    let schedule = self.cfg.schedule.clone();
+                    if let Some(reloaded) = self.reload(cfg, &services).await {
+                        services = reloaded;
+                        if self.cfg.schedule != schedule {
+                            ticker.set_schedule(self.cfg.schedule.clone());
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if !leadership.is_leading() {
+                info!("Standing by, another replica leads");
+                continue;
+            }
+
+            if let Some(reason) = self.cfg.schedule.closed_reason(Local::now()) {
+                info!("Skipping sync-cycle, {reason}");
+                continue;
+            }
+
+            match &scope {
+                TriggerScope::All => {
+                    // everything queued until now is covered by this cycle
+                    triggers.clear();
+                    info!("=== sync-cycle #{cycle} ({}) ===", Local::now());
+                }
+                TriggerScope::Uids(uids) => {
+                    info!(
+                        "=== partial sync of {} dashboard(s) ({}) ===",
+                        uids.len(),
+                        Local::now()
+                    );
+                }
+            }
 
             let start = Instant::now();
-            if let Err(e) = self.run_single_cycle(cycle).await {
-                error!("cycle #{cycle} failed: {e}");
+            // copies missing for longer than this count as deleted, so after a pause,
+            // e.g. a blackout, the time since the previous cycle counts
+            let interval = last_cycle
+                .map(|last| start - last)
+                .unwrap_or_default()
+                .max(self.cfg.schedule.nominal_interval(Local::now()));
+            let interval_mins = interval.as_secs().div_ceil(60).max(1);
+            let all_orgs = async {
+                for service in &services {
+                    if let Some(org) = &service.org {
+                        info!("--- org \"{org}\" ---");
+                    }
+                    let res = match &scope {
+                        TriggerScope::All => service.run_single_cycle(cycle, interval_mins).await,
+                        TriggerScope::Uids(uids) => {
+                            service.run_partial_cycle(cycle, interval_mins, uids).await
+                        }
+                    };
+                    if let Err(e) = res {
+                        error!("cycle #{cycle} failed: {e}");
+                    }
+                }
+            };
+            tokio::select! {
+                _ = all_orgs => {}
+                _ = shutdown.deadline(self.cfg.shutdown_timeout) => {
+                    error!(
+                        "cycle #{cycle} still running {:?} after shutdown was requested, aborting it",
+                        self.cfg.shutdown_timeout
+                    );
+                    break;
+                }
+                _ = leadership.lost() => {
+                    error!("cycle #{cycle} aborted, this replica lost the lead");
+                }
+            }
+            if scope != TriggerScope::All {
+                info!("=== finished partial sync in {:?}", start.elapsed());
+                continue;
             }
             info!("=== finished sync-cycle #{cycle} in {:?}", start.elapsed());
+            // partial syncs don't check the other dashboards, so only full cycles count
+            last_cycle = Some(start);
             cycle += 1;
         }
+
+        for service in &services {
+            if let Err(e) = service.cache.read().await.persist().await {
+                warn!("could not persist dashboard cache: {e}");
+            }
+        }
+        leadership.resign().await;
+        info!("Shut down cleanly");
+        Ok(())
+    }
+
+    /// Puts a new config to use, unless its instances can't be split by org.
+    /// Returns the services to run from now on.
+    async fn reload(
+        &mut self,
+        mut cfg: Config,
+        services: &[SyncService],
+    ) -> Option<Vec<SyncService>> {
+        if cfg.cache_file != self.cfg.cache_file {
+            warn!("A changed cache_file only takes effect after a restart");
+            cfg.cache_file = self.cfg.cache_file.clone();
+        }
+        if cfg.leader_election != self.cfg.leader_election {
+            warn!("A changed leader_election only takes effect after a restart");
+            cfg.leader_election = self.cfg.leader_election.clone();
+        }
+        if cfg.trigger != self.cfg.trigger {
+            warn!("A changed trigger only takes effect after a restart");
+            cfg.trigger = self.cfg.trigger.clone();
+        }
+
+        let differences = cfg.differences(&self.cfg);
+        let previous = std::mem::replace(&mut self.cfg, Arc::new(cfg));
+        match self.split_by_org(services).await {
+            Ok(reloaded) => {
+                if differences.is_empty() {
+                    info!("Reloaded config, nothing changed");
+                }
+                for difference in differences {
+                    info!("Reloaded config: {difference}");
+                }
+                self.cfg.dbg_print();
+                Some(reloaded)
+            }
+            Err(e) => {
+                error!("Could not apply the reloaded config, keeping the current one: {e}");
+                self.cfg = previous;
+                None
+            }
+        }
+    }
+
+    fn instance(&self, name: &str) -> Option<&GrafanaInstance> {
+        self.cfg.instances.iter().find(|i| i.name() == name)
+    }
+
+    /// The configured source instance, or the first instance if none is configured. Instances
+    /// expanded to all orgs of their server are found by the server's url as well.
+    /// `None` if the configured source doesn't take part in this service, e.g. in this org.
+    fn source_instance(&self, configured: Option<&str>, section: &str) -> Option<&str> {
+        let Some(name) = configured else {
+            return self.cfg.instances.first().map(|i| i.name());
+        };
+        let source = self.instance(name).or_else(|| {
+            self.cfg
+                .instances
+                .iter()
+                .find(|i| i.shared_org().is_some() && i.base_url() == name)
+        });
+        if source.is_none() {
+            warn!("Not syncing {section}, their source {name} takes no part in this sync");
+        }
+        source.map(|i| i.name())
     }
 
     /* Core Logic */
 
-    async fn run_single_cycle(&self, cycle: usize) -> Result<(), GSError> {
+    async fn run_single_cycle(&self, cycle: usize, interval_mins: u64) -> Result<(), GSError> {
+        let targets = self.resolve_targets().await;
+        match self.usable_part(&targets) {
+            Some(service) => service.full_cycle_with(targets, cycle, interval_mins).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn full_cycle_with(
+        &self,
+        targets: TargetContexts,
+        cycle: usize,
+        interval_mins: u64,
+    ) -> Result<(), GSError> {
+        let mut state = DashboardState::new(self.cfg.instances.len());
+        self.collect_dashboards(&mut state, &targets).await?;
+
+        state.print_data_stats();
+
+        let dashboards = self.merge_dashboards(&state, cycle, interval_mins);
+        self.check_dependencies_upfront(&dashboards, &targets)
+            .await?;
+
+        let library = self.collect_library_panels(&state, &targets).await?;
+
+        let mut folder_map = self.index_folders().await;
+        let alerts = match &self.cfg.alert_rules {
+            Some(selector) => {
+                self.collect_alert_groups(selector, &folder_map, &targets)
+                    .await?
+            }
+            None => AlertState::default(),
+        };
+        alerts.print_data_stats();
+
+        let synced_folders: HashSet<String> = state
+            .unique_folders()
+            .into_iter()
+            .chain(library.unique_folders())
+            .chain(alerts.unique_folders())
+            .map(|c| c.to_owned())
+            .collect();
+        self.mirror_folders(&mut folder_map, &synced_folders).await;
+
+        self.replicate_library_panels(&library, &folder_map, &targets)
+            .await?;
+
+        if let Some(selector) = &self.cfg.notifications {
+            self.sync_notifications(selector).await?;
+        }
+
+        self.replicate_alert_groups(&alerts, &folder_map, &targets)
+            .await?;
+
+        self.replicate_dashboards(dashboards, &folder_map, targets)
+            .await?;
+
+        if let Some(annotations) = &self.cfg.annotations {
+            self.sync_annotations(annotations, &state.dashboard_uids())
+                .await;
+        }
+
+        if let Some(displays) = &self.cfg.displays {
+            self.sync_displays(displays, &state.dashboard_uids()).await?;
+        }
+
+        self.purge_empty_folders(&folder_map, &synced_folders).await
+    }
+
+    /// Syncs only the dashboards with the given UIDs, with the library panels and folders
+    /// they need. Everything else is left to the full cycles.
+    async fn run_partial_cycle(
+        &self,
+        cycle: usize,
+        interval_mins: u64,
+        uids: &BTreeSet<String>,
+    ) -> Result<(), GSError> {
+        let targets = self.resolve_targets().await;
+        match self.usable_part(&targets) {
+            Some(service) => service.partial_cycle_with(targets, cycle, interval_mins, uids).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn partial_cycle_with(
+        &self,
+        targets: TargetContexts,
+        cycle: usize,
+        interval_mins: u64,
+        uids: &BTreeSet<String>,
+    ) -> Result<(), GSError> {
         let mut state = DashboardState::new(self.cfg.instances.len());
-        self.collect_dashboards(&mut state).await?;
+        self.collect_requested_dashboards(&mut state, &targets, uids)
+            .await?;
 
         state.print_data_stats();
 
-        let folder_map = self.
-            mirror_folders(state.unique_folders().iter().map(|&c| c.to_owned()).collect())
-            .await;
+        let dashboards = self.merge_dashboards(&state, cycle, interval_mins);
+        self.check_dependencies_upfront(&dashboards, &targets)
+            .await?;
+
+        let library = self.collect_library_panels(&state, &targets).await?;
+
+        let mut folder_map = self.index_folders().await;
+        let synced_folders: HashSet<String> = state
+            .unique_folders()
+            .into_iter()
+            .chain(library.unique_folders())
+            .map(|c| c.to_owned())
+            .collect();
+        self.mirror_folders(&mut folder_map, &synced_folders).await;
 
-        let dashboards = Arc::new(
+        self.replicate_library_panels(&library, &folder_map, &targets)
+            .await?;
+
+        self.replicate_dashboards(dashboards, &folder_map, targets)
+            .await
+    }
+
+    /// Decides per UID which copy wins, or whether the dashboard was deleted.
+    fn merge_dashboards(
+        &self,
+        state: &DashboardState,
+        cycle: usize,
+        interval_mins: u64,
+    ) -> Arc<Vec<(String, RwLock<Option<FullDashboard>>)>> {
+        let mask = Pipeline::merged(
+            self.cfg
+                .instances
+                .iter()
+                .map(|i| &i.options().transforms),
+        );
+        let transformed = self
+            .cfg
+            .instances
+            .iter()
+            .filter(|i| !i.options().transforms.is_empty())
+            .map(|i| i.name())
+            .collect();
+        Arc::new(
             state
-                .diff(cycle != 0, self.cfg.sync_rate_mins)
+                .diff(cycle != 0, interval_mins, &mask, &transformed)
                 .into_iter()
                 .map(|(uid, d)| (uid.to_owned(), RwLock::new(d)))
                 .collect::<Vec<_>>(),
-        );
+        )
+    }
+
+    /// The service restricted to the instances whose targets could be resolved, so that the
+    /// others sit out the cycle instead of having their copies look deleted.
+    /// `None` if fewer than two instances are left.
+    fn usable_part(&self, targets: &TargetContexts) -> Option<SyncService> {
+        if targets.len() == self.cfg.instances.len() {
+            return Some(self.clone());
+        }
+        if targets.len() < 2 {
+            warn!("Skipping the cycle, fewer than two instances are usable");
+            return None;
+        }
+        let mut cfg = (*self.cfg).clone();
+        cfg.instances.retain(|i| targets.contains_key(i.name()));
+        Some(SyncService {
+            cfg: Arc::new(cfg),
+            cache: self.cache.clone(),
+            org: self.org.clone(),
+        })
+    }
 
-        self.replicate_dashboards(dashboards, &folder_map).await?;
+    /// With a `fail` policy, checks every dashboard about to be imported onto every instance
+    /// before anything is written, so that a missing dependency aborts the cycle as a whole.
+    async fn check_dependencies_upfront(
+        &self,
+        dashboards: &[(String, RwLock<Option<FullDashboard>>)],
+        targets: &TargetContexts,
+    ) -> Result<(), GSError> {
+        let fail_datasources = self.cfg.missing_datasources == MissingDependencyPolicy::Fail;
+        let fail_plugins = self.cfg.missing_panel_plugins == MissingDependencyPolicy::Fail;
+        if !fail_datasources && !fail_plugins {
+            return Ok(());
+        }
+
+        for instance in &self.cfg.instances {
+            let Some(target) = targets.get(instance.name()) else {
+                continue;
+            };
+            for (_, guard) in dashboards {
+                let Some(d) = &*guard.read().await else {
+                    continue;
+                };
+                let local = prepare_import(instance, d, target)?;
+                let (datasources, plugins) = missing_dependencies(&local, target);
+                let failing = [
+                    ("datasources", datasources, fail_datasources),
+                    ("panel plugins", plugins, fail_plugins),
+                ];
+                for (kind, missing, fail) in failing {
+                    if fail && !missing.is_empty() {
+                        return Err(GSError::MissingDependencies(
+                            instance.name().to_owned(),
+                            local.dashboard.title.clone(),
+                            kind,
+                            missing.join(", "),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 
-        self.purge_empty_folders().await
+    /// Resolves what is needed to import onto each instance. Instances within one of their
+    /// blackouts, or for which that fails, are left out and logged.
+    async fn resolve_targets(&self) -> TargetContexts {
+        let validate_datasources = self.cfg.missing_datasources != MissingDependencyPolicy::Ignore;
+        let validate_plugins = self.cfg.missing_panel_plugins != MissingDependencyPolicy::Ignore;
+        let now = Local::now();
+
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .filter(|instance| {
+                let blackouts = &instance.options().blackouts;
+                match blackouts.iter().find(|b| b.contains(now)) {
+                    Some(blackout) => {
+                        info!(
+                            "{}: sitting out this cycle, blackout until {}",
+                            instance.name(),
+                            blackout.to
+                        );
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .map(|instance| async move {
+                let _slot = instance.acquire_slot().await;
+                let target = async {
+                    let datasources =
+                        DatasourceMap::resolve(instance, validate_datasources).await?;
+                    let panel_plugins = if validate_plugins {
+                        match instance.get_plugins("panel").await {
+                            Ok(plugins) => Some(plugins.into_iter().map(|p| p.id).collect()),
+                            Err(e) if e.is_forbidden() => {
+                                warn!(
+                                    "{}: not allowed to list plugins, not checking them",
+                                    instance.name()
+                                );
+                                None
+                            }
+                            Err(e) => return Err(e),
+                        }
+                    } else {
+                        None
+                    };
+                    Ok::<_, GSError>(TargetContext {
+                        datasources,
+                        panel_plugins,
+                    })
+                };
+                (instance.name().to_owned(), target.await)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut targets = TargetContexts::new();
+        while let Some((name, res)) = tasks.next().await {
+            match res {
+                Ok(target) => {
+                    targets.insert(name, target);
+                }
+                Err(e) => error!("{name}: sitting out this cycle, could not resolve it: {e}"),
+            }
+        }
+        targets
     }
 
+    /// Collects the selected dashboards of all instances into `state`, with their
+    /// datasources translated to the shared identifiers so that copies compare equal.
     async fn collect_dashboards(
         &self,
         state: &mut DashboardState,
+        targets: &TargetContexts,
     ) -> Result<(), GSError> {
         let mut tasks = FuturesUnordered::new();
 
         for instance in &self.cfg.instances {
             let instance = instance.clone();
-            let tag = self.cfg.sync_tag.clone();
-            tasks.push(async move { fetch_full_dashboards(instance, &tag).await });
+            let cfg = self.cfg.clone();
+            let cache = self.cache.clone();
+            tasks.push(async move { fetch_full_dashboards(instance, &cfg.selector, &cache).await });
         }
 
+        let mut sets = Vec::new();
         while let Some(res) = tasks.next().await {
-            let (base_url, dashboards) = res?;
+            sets.push(res?);
+        }
+
+        let mut cache = self.cache.write().await;
+        let untagged = detect_untagged(&mut cache, &sets);
+
+        let mut untag_jobs = Vec::new();
+        for (base_url, dashboards, _) in &mut sets {
+            let (detached, kept) = std::mem::take(dashboards)
+                .into_iter()
+                .partition(|d| cache.is_detached(&d.dashboard.uid));
+            *dashboards = kept;
+
+            if self.cfg.untag_policy == UntagPolicy::Propagate {
+                untag_jobs.extend(
+                    detached
+                        .into_iter()
+                        .filter(|d| untagged.contains(&d.dashboard.uid))
+                        .map(|d| (base_url.clone(), d)),
+                );
+            }
+        }
+
+        for (base_url, mut dashboards, _) in sets {
+            cache.replace_instance(&base_url, &dashboards);
+            if let Some(target) = targets.get(&base_url) {
+                dashboards
+                    .iter_mut()
+                    .for_each(|d| target.datasources.to_shared(d));
+            }
             state.add_set(base_url, dashboards);
         }
+        if let Err(e) = cache.persist().await {
+            warn!("could not persist dashboard cache: {e}");
+        }
+        drop(cache);
+
+        self.propagate_untag(untag_jobs).await;
 
         Ok(())
     }
 
-    async fn mirror_folders(&self, folders: HashSet<String>) -> FolderMap {
-        let folders = Arc::new(folders);
-        let mut tasks = FuturesUnordered::new();
+    /// Collects the copies of the requested dashboards on all instances into `state`.
+    /// Dashboards which are detached, or which an instance has but doesn't select, are
+    /// left out: whether they were untagged is up to a full cycle to decide.
+    async fn collect_requested_dashboards(
+        &self,
+        state: &mut DashboardState,
+        targets: &TargetContexts,
+        uids: &BTreeSet<String>,
+    ) -> Result<(), GSError> {
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| async move {
+                let mut jobs = uids
+                    .iter()
+                    .map(|uid| async move {
+                        let _slot = instance.acquire_slot().await;
+                        instance.find_dashboard_full(uid).await
+                    })
+                    .collect::<FuturesUnordered<_>>();
 
-        for instance in &self.cfg.instances {
-            let instance = instance.clone();
-            let folders = folders.clone();
-            tasks.push(async move { ensure_folders_on_instance(instance, &folders).await });
+                let mut dashboards = Vec::new();
+                while let Some(res) = jobs.next().await {
+                    dashboards.extend(res?);
+                }
+
+                let paths = {
+                    let _slot = instance.acquire_slot().await;
+                    instance.get_folder_paths().await?
+                };
+                let unselected = dashboards
+                    .iter()
+                    .map(FullDashboard::summary)
+                    .filter(|d| !self.cfg.selector.matches(d, &paths.path_of(d)))
+                    .map(|d| d.uid)
+                    .collect::<Vec<_>>();
+                Ok::<_, GSError>((instance.name().to_owned(), dashboards, unselected))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut sets = Vec::new();
+        while let Some(res) = tasks.next().await {
+            sets.push(res?);
+        }
+
+        let cache = self.cache.read().await;
+        let mut found = BTreeSet::new();
+        let mut skipped = BTreeSet::new();
+        for (_, dashboards, unselected) in &sets {
+            skipped.extend(unselected.iter().cloned());
+            for d in dashboards {
+                found.insert(d.dashboard.uid.as_str());
+                if cache.is_detached(&d.dashboard.uid) {
+                    skipped.insert(d.dashboard.uid.clone());
+                }
+            }
+        }
+        drop(cache);
+        for uid in uids.iter().filter(|uid| !found.contains(uid.as_str())) {
+            warn!("Dashboard '{uid}' exists on no instance");
+        }
+        for uid in &skipped {
+            info!("Not syncing '{uid}', it is detached or not selected on every instance");
         }
 
+        for (name, mut dashboards, _) in sets {
+            dashboards.retain(|d| !skipped.contains(&d.dashboard.uid));
+            if let Some(target) = targets.get(&name) {
+                dashboards
+                    .iter_mut()
+                    .for_each(|d| target.datasources.to_shared(d));
+            }
+            state.add_set(name, dashboards);
+        }
+        Ok(())
+    }
+
+    /// Removes the selecting tags from the remaining copies of untagged dashboards.
+    async fn propagate_untag(&self, jobs: Vec<(String, FullDashboard)>) {
+        let tags = self.cfg.selector.included_tags();
+        let mut tasks = jobs
+            .into_iter()
+            .filter_map(|(base_url, mut dashboard)| {
+                let instance = self.instance(&base_url)?;
+                dashboard.dashboard.tags.retain(|t| !tags.contains(t.as_str()));
+                Some(async move {
+                    let _slot = instance.acquire_slot().await;
+                    info!(
+                        "{}: removing sync tags from '{}'",
+                        instance.name(),
+                        dashboard.dashboard.uid
+                    );
+                    if let Err(e) = instance.save_dashboard(&dashboard).await {
+                        error!(
+                            "{}: could not untag '{}': {e}",
+                            instance.name(),
+                            dashboard.dashboard.uid
+                        );
+                    }
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while tasks.next().await.is_some() {}
+    }
+
+    /// Fetches every instance's copy of the library panels used by any synced dashboard.
+    async fn collect_library_panels(
+        &self,
+        state: &DashboardState,
+        targets: &TargetContexts,
+    ) -> Result<LibraryState, GSError> {
+        let uids: Vec<String> = state
+            .library_panel_uids()
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let mut library = LibraryState::default();
+        if uids.is_empty() {
+            return Ok(library);
+        }
+
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| {
+                let uids = &uids;
+                async move {
+                    let mut jobs = uids
+                        .iter()
+                        .map(|uid| async move {
+                            let _slot = instance.acquire_slot().await;
+                            instance.get_library_element(uid).await
+                        })
+                        .collect::<FuturesUnordered<_>>();
+
+                    let mut elements = Vec::new();
+                    while let Some(res) = jobs.next().await {
+                        elements.extend(res?);
+                    }
+                    Ok::<_, GSError>((instance.name().to_owned(), elements))
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = tasks.next().await {
+            let (base_url, mut elements) = res?;
+            if let Some(target) = targets.get(&base_url) {
+                elements
+                    .iter_mut()
+                    .for_each(|e| target.datasources.value_to_shared(&mut e.model));
+            }
+            library.add_set(base_url, elements);
+        }
+        Ok(library)
+    }
+
+    /// Writes the newest copy of each library panel to the instances where it's outdated,
+    /// so that the dashboards using it can be imported afterwards.
+    async fn replicate_library_panels(
+        &self,
+        library: &LibraryState,
+        folder_map: &FolderMap,
+        targets: &TargetContexts,
+    ) -> Result<(), GSError> {
+        let mut jobs = FuturesUnordered::new();
+
+        for update in library.diff() {
+            for (base_url, current_version) in update.targets {
+                let Some(instance) = self.instance(base_url) else {
+                    continue;
+                };
+                let folder_uid = folder_map
+                    .get(base_url)
+                    .and_then(|f| f.get(&update.element.meta.folder_name))
+                    .map(|f| f.uid.clone());
+
+                let mut element = update.element.clone();
+                if let Some(target) = targets.get(base_url) {
+                    target.datasources.value_to_local(&mut element.model);
+                }
+
+                jobs.push(async move {
+                    let _slot = instance.acquire_slot().await;
+                    instance
+                        .upsert_library_element(&element, folder_uid.as_deref(), current_version)
+                        .await
+                });
+            }
+        }
+
+        while let Some(res) = jobs.next().await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Fetches every instance's rule groups which contain at least one selected alert rule.
+    async fn collect_alert_groups(
+        &self,
+        selector: &AlertRuleSelector,
+        folder_map: &FolderMap,
+        targets: &TargetContexts,
+    ) -> Result<AlertState, GSError> {
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let folders = folder_map.get(instance.name())?;
+                Some(async move {
+                    let rules = {
+                        let _slot = instance.acquire_slot().await;
+                        instance.get_alert_rules().await?
+                    };
+                    let keys = rules
+                        .iter()
+                        .filter(|r| selector.matches(&r.labels))
+                        .map(|r| (r.folder_uid.as_str(), r.rule_group.as_str()))
+                        .collect::<HashSet<_>>();
+
+                    let mut jobs = keys
+                        .into_iter()
+                        .map(|(folder_uid, group)| async move {
+                            let _slot = instance.acquire_slot().await;
+                            let group = instance.get_alert_rule_group(folder_uid, group).await?;
+                            Ok::<_, GSError>((folder_uid, group))
+                        })
+                        .collect::<FuturesUnordered<_>>();
+
+                    let mut groups = Vec::new();
+                    while let Some(res) = jobs.next().await {
+                        let (folder_uid, mut group) = res?;
+                        let Some(folder) = folders.by_uid(folder_uid) else {
+                            warn!("{}: unknown folder of alert group '{}'", instance.name(), group.title);
+                            continue;
+                        };
+                        if let Some(target) = targets.get(instance.name()) {
+                            group
+                                .rules
+                                .iter_mut()
+                                .for_each(|r| target.datasources.value_to_shared(r));
+                        }
+                        groups.push(AlertGroupCopy::new(folder.title.clone(), group));
+                    }
+                    Ok::<_, GSError>((instance.name().to_owned(), groups))
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut alerts = AlertState::default();
+        while let Some(res) = tasks.next().await {
+            let (base_url, groups) = res?;
+            alerts.add_set(base_url, groups);
+        }
+        Ok(alerts)
+    }
+
+    /// Writes the most recent copy of each rule group into the same-named folder of the
+    /// instances where it's outdated.
+    async fn replicate_alert_groups(
+        &self,
+        alerts: &AlertState,
+        folder_map: &FolderMap,
+        targets: &TargetContexts,
+    ) -> Result<(), GSError> {
+        let mut jobs = FuturesUnordered::new();
+
+        for update in alerts.diff() {
+            for base_url in update.targets {
+                let Some(instance) = self.instance(base_url) else {
+                    continue;
+                };
+                let folder_title = &update.source.folder_title;
+                let Some(folder) = folder_map.get(base_url).and_then(|f| f.get(folder_title))
+                else {
+                    error!("{base_url}: folder '{folder_title}' missing, skipping its alert rules");
+                    continue;
+                };
+
+                let mut group = update.source.group.clone();
+                group.folder_uid = folder.uid.clone();
+                for rule in &mut group.rules {
+                    if let Some(target) = targets.get(base_url) {
+                        target.datasources.value_to_local(rule);
+                    }
+                    if let Some(obj) = rule.as_object_mut() {
+                        obj.insert("folderUID".into(), folder.uid.clone().into());
+                        obj.insert("ruleGroup".into(), group.title.clone().into());
+                    }
+                }
+
+                jobs.push(async move {
+                    let _slot = instance.acquire_slot().await;
+                    instance.put_alert_rule_group(&group).await
+                });
+            }
+        }
+
+        while let Some(res) = jobs.next().await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Brings the selected notification settings of all instances in line with the source
+    /// instance. Runs before alert rules are replicated, as those may route to contact points.
+    async fn sync_notifications(&self, selector: &NotificationSelector) -> Result<(), GSError> {
+        let Some(source) = self.source_instance(selector.source.as_deref(), "notifications") else {
+            return Ok(());
+        };
+
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| async move {
+                let set = fetch_notification_set(instance, selector.label.is_some()).await;
+                (instance.name().to_owned(), set)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut state = NotificationState::default();
+        while let Some((base_url, set)) = tasks.next().await {
+            match set {
+                Ok(set) => state.add_set(base_url, set),
+                Err(e) if base_url == source => return Err(e),
+                Err(e) => error!("{base_url}: could not fetch notification settings: {e}"),
+            }
+        }
+        state.print_data_stats();
+
+        let cache = self.cache.read().await;
+        let updates = state.diff(source, selector, |target, contact_point| {
+            let Some(secrets) = self.contact_point_secrets(target, &contact_point.name) else {
+                return true;
+            };
+            cache.secrets_digest(target, &contact_point.name) == Some(secrets_digest(secrets))
+        });
+        drop(cache);
+
+        let mut jobs = updates
+            .into_iter()
+            .filter_map(|update| {
+                let instance = self.instance(update.target)?;
+                let written = update
+                    .contact_points
+                    .iter()
+                    .map(|(c, _)| c.name.clone())
+                    .collect::<Vec<_>>();
+                Some(async move {
+                    update_notifications_on_instance(instance, update).await?;
+                    Ok::<_, GSError>((instance.name(), written))
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = jobs.next().await {
+            let (target, written) = res?;
+            let mut cache = self.cache.write().await;
+            for name in written {
+                if let Some(secrets) = self.contact_point_secrets(target, &name) {
+                    cache.set_secrets_digest(target, &name, secrets_digest(secrets));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The secure settings configured for a contact point on an instance.
+    fn contact_point_secrets(
+        &self,
+        instance: &str,
+        contact_point: &str,
+    ) -> Option<&HashMap<String, EncryptedCredential>> {
+        self.instance(instance)?
+            .options()
+            .contact_point_secrets
+            .get(contact_point)
+    }
+
+    /// Syncs the recent annotations of every synced dashboard. Failures only affect the
+    /// dashboard they occur on.
+    async fn sync_annotations(&self, settings: &AnnotationSettings, uids: &HashSet<&str>) {
+        let to = Local::now().timestamp_millis();
+        let from = to - settings.window_days as i64 * 24 * 60 * 60 * 1000;
+
+        let mut tasks = uids
+            .iter()
+            .map(|&uid| async move {
+                let res = self.sync_dashboard_annotations(uid, from, to).await;
+                (uid, res)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some((uid, res)) = tasks.next().await {
+            if let Err(e) = res {
+                error!("could not sync annotations of dashboard '{uid}': {e}");
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        cache.retain_annotations(uids);
+        if let Err(e) = cache.persist().await {
+            warn!("could not persist dashboard cache: {e}");
+        }
+    }
+
+    async fn sync_dashboard_annotations(
+        &self,
+        uid: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<(), GSError> {
+        let mut fetches = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| async move {
+                let _slot = instance.acquire_slot().await;
+                let annotations = instance.get_annotations(uid, from, to).await;
+                (instance.name().to_owned(), annotations)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut state = AnnotationState::new(uid.to_owned());
+        while let Some((base_url, annotations)) = fetches.next().await {
+            let annotations = annotations?;
+            if annotations.len() >= ANNOTATION_LIMIT {
+                warn!("{base_url}: too many annotations on dashboard '{uid}', not syncing them");
+                return Ok(());
+            }
+            state.add_set(base_url, annotations);
+        }
+
+        let known = self.cache.read().await.known_annotations(uid);
+        let (changes, complete) = state.diff(&known);
+
+        let mut jobs = changes
+            .into_iter()
+            .filter_map(|change| {
+                let target = match &change {
+                    AnnotationChange::Create { target, .. }
+                    | AnnotationChange::Update { target, .. }
+                    | AnnotationChange::Delete { target, .. } => *target,
+                };
+                let instance = self.instance(target)?;
+                Some(async move {
+                    let _slot = instance.acquire_slot().await;
+                    match change {
+                        AnnotationChange::Create { body, .. } => {
+                            instance.create_annotation(&body).await
+                        }
+                        AnnotationChange::Update { id, body, .. } => {
+                            instance.update_annotation(id, &body).await
+                        }
+                        AnnotationChange::Delete { id, .. } => {
+                            instance.delete_annotation(id).await
+                        }
+                    }
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = jobs.next().await {
+            res?;
+        }
+
+        self.cache.write().await.set_known_annotations(uid, complete);
+        Ok(())
+    }
+
+    /// Copies the source instance's playlists and home dashboard to the other instances.
+    /// Runs after the dashboards were replicated, so the dashboards shown exist everywhere.
+    async fn sync_displays(
+        &self,
+        displays: &DisplaySettings,
+        synced_uids: &HashSet<&str>,
+    ) -> Result<(), GSError> {
+        let Some(source) = self.source_instance(displays.source.as_deref(), "displays") else {
+            return Ok(());
+        };
+
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| async move {
+                let set = fetch_display_set(instance, displays).await;
+                (instance.name().to_owned(), set)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut state = DisplayState::default();
+        while let Some((base_url, set)) = tasks.next().await {
+            match set {
+                Ok(set) => state.add_set(base_url, set),
+                Err(e) if base_url == source => return Err(e),
+                Err(e) => error!("{base_url}: could not fetch playlists and preferences: {e}"),
+            }
+        }
+        state.print_data_stats();
+
+        let synced_tags = self.cfg.selector.included_tags();
+        let mut jobs = state
+            .diff(source, synced_uids, &synced_tags)
+            .into_iter()
+            .filter_map(|update| {
+                let instance = self.instance(update.target)?;
+                Some(update_displays_on_instance(instance, update))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = jobs.next().await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Indexes the folders of every instance once per cycle.
+    /// Instances whose folders can't be listed are left out of the map.
+    async fn index_folders(&self) -> FolderMap {
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .map(|instance| async move {
+                let _slot = instance.acquire_slot().await;
+                (instance.name().to_owned(), instance.get_folder_index().await)
+            })
+            .collect::<FuturesUnordered<_>>();
+
         let mut map = FolderMap::new();
-        while let Some((url, folders)) = tasks.next().await {
-            map.insert(url, folders);
+        while let Some((url, index)) = tasks.next().await {
+            match index {
+                Ok(index) => {
+                    map.insert(url, index);
+                }
+                Err(e) => error!("{url}: could not list folders: {e}"),
+            }
         }
         map
     }
 
+    /// Creates the synced folders which are missing on an instance.
+    async fn mirror_folders(&self, folder_map: &mut FolderMap, folders: &HashSet<String>) {
+        let mut tasks = folder_map
+            .iter_mut()
+            .filter_map(|(url, index)| {
+                let instance = self.instance(url)?;
+                Some(ensure_folders_on_instance(instance, index, folders))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while tasks.next().await.is_some() {}
+    }
+
     async fn replicate_dashboards(
         &self,
         dashboards: Arc<Vec<(String, RwLock<Option<FullDashboard>>)>>,
         folder_map: &FolderMap,
+        mut targets: TargetContexts,
     ) -> Result<(), GSError> {
         let folder_map = Arc::new(folder_map.clone());
         let mut tasks = FuturesUnordered::new();
@@ -128,8 +1288,10 @@ impl SyncService {
             let instance = instance.clone();
             let dbs = dashboards.clone();
             let folders = folder_map.clone();
+            let target = targets.remove(instance.name()).unwrap_or_default();
+            let cfg = self.cfg.clone();
             tasks.push(tokio::spawn(async move {
-                replicate_dashboards_on_instance(folders, dbs, instance).await
+                replicate_dashboards_on_instance(folders, dbs, target, cfg, instance).await
             }));
         }
 
@@ -139,53 +1301,291 @@ impl SyncService {
         Ok(())
     }
 
-    async fn purge_empty_folders(&self) -> Result<(), GSError> {
-        for instance in &self.cfg.instances {
-            instance.remove_empty_folders().await?;
+    async fn purge_empty_folders(
+        &self,
+        folder_map: &FolderMap,
+        synced_folders: &HashSet<String>,
+    ) -> Result<(), GSError> {
+        let mut tasks = self
+            .cfg
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let index = folder_map.get(instance.name())?;
+                Some(instance.remove_empty_folders(index, synced_folders))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some(res) = tasks.next().await {
+            res?;
         }
         Ok(())
     }
 }
 
+/// Takes UIDs back into the sync which an instance selects again, and detaches
+/// those which were synced before but lost their selection on some instance.
+fn detect_untagged(cache: &mut DashboardCache, sets: &[InstanceSet]) -> HashSet<String> {
+    for (base_url, dashboards, unselected) in sets {
+        for uid in cache.reattach(base_url, dashboards, unselected) {
+            info!("{base_url}: '{uid}' is selected again, resuming its sync");
+        }
+    }
+
+    let mut untagged = HashSet::new();
+    for (base_url, _, unselected) in sets {
+        for uid in cache.deselected(base_url, unselected) {
+            if cache.is_detached(uid) {
+                continue;
+            }
+            info!("{base_url}: '{uid}' is no longer selected, detaching it from the sync");
+            cache.detach(uid, base_url);
+            untagged.insert(uid.to_owned());
+        }
+    }
+    untagged
+}
+
+/// The cache file of an org's service: `cache.json` becomes `cache.<org>.json`.
+fn org_cache_file(path: &Path, org: &str) -> PathBuf {
+    let org = org
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+        .collect::<String>();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{org}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{org}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Fetches all selected dashboards of an instance. Dashboards whose version didn't change
+/// since the last cycle are served from the cache instead of being downloaded again.
+///
+/// Grafana's `/api/search` lists neither versions nor update times, so against it every
+/// dashboard still costs one version lookup per cycle, just no full download.
 async fn fetch_full_dashboards(
     instance: GrafanaInstance,
-    tag: &str
-) -> Result<(String, Vec<FullDashboard>), GSError> {
-    let mut dashboards = Vec::new();
-    for d in instance.get_dashboards_by_tag(tag).await? {
-        dashboards.push(instance.get_dashboard_full(&d.uid).await?);
+    selector: &Selector,
+    cache: &RwLock<DashboardCache>,
+) -> Result<InstanceSet, GSError> {
+    let listing = {
+        let _slot = instance.acquire_slot().await;
+        instance.get_selected_dashboards(selector).await?
+    };
+    let listed = listing.selected;
+
+    let mut jobs = listed
+        .iter()
+        .map(|d| {
+            let instance = &instance;
+            async move {
+                let cached = cache.read().await.get_listed(instance.name(), d).cloned();
+                if let Some(dashboard) = cached {
+                    debug!("{}: '{}' unchanged since the last cycle", instance.name(), d.uid);
+                    return Ok(dashboard);
+                }
+
+                // Listings without a version or update time need a version lookup per dashboard.
+                // It is only an optimisation, fall back to a full fetch if it fails
+                if d.version.is_none() && d.updated.is_none() {
+                    let version = {
+                        let _slot = instance.acquire_slot().await;
+                        instance.get_dashboard_version(&d.uid).await
+                    };
+                    if let Ok(Some(version)) = version {
+                        let cached = cache
+                            .read()
+                            .await
+                            .get(instance.name(), &d.uid, version)
+                            .cloned();
+                        if let Some(dashboard) = cached {
+                            debug!(
+                                "{}: '{}' unchanged at version {version}",
+                                instance.name(),
+                                d.uid
+                            );
+                            return Ok(dashboard);
+                        }
+                    }
+                }
+                let _slot = instance.acquire_slot().await;
+                instance.get_dashboard_full(&d.uid).await
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut dashboards = Vec::with_capacity(listed.len());
+    while let Some(res) = jobs.next().await {
+        dashboards.push(res?);
     }
-    Ok((instance.base_url().to_owned(), dashboards))
+    Ok((instance.name().to_owned(), dashboards, listing.unselected_uids))
 }
 
 async fn ensure_folders_on_instance(
-    instance: GrafanaInstance,
+    instance: &GrafanaInstance,
+    index: &mut FolderIndex,
     folders: &HashSet<String>,
-) -> (String, HashMap<String, Folder>) {
-    let mut map = HashMap::new();
-    for name in folders {
-        if name == "General" {
-            continue;
+) {
+    let mut jobs = folders
+        .iter()
+        .filter(|name| name.as_str() != "General" && index.get(name).is_none())
+        .map(|name| async move {
+            let _slot = instance.acquire_slot().await;
+            (name, instance.create_folder(name).await)
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut created = Vec::new();
+    while let Some((name, res)) = jobs.next().await {
+        match res {
+            Ok(folder) => created.push(folder),
+            Err(e) => error!("{}: could not create folder '{name}': {e}", instance.name()),
         }
-        match instance.ensure_folder(name).await {
-            Ok(folder) => {
-                map.insert(folder.title.clone(), folder);
+    }
+
+    for folder in created {
+        index.insert(folder);
+    }
+}
+
+async fn fetch_display_set(
+    instance: &GrafanaInstance,
+    displays: &DisplaySettings,
+) -> Result<DisplaySet, GSError> {
+    let mut set = DisplaySet::default();
+
+    if displays.playlists {
+        let summaries = {
+            let _slot = instance.acquire_slot().await;
+            instance.get_playlists().await?
+        };
+        let mut jobs = summaries
+            .iter()
+            .map(|p| async move {
+                let _slot = instance.acquire_slot().await;
+                instance.get_playlist(&p.uid).await
+            })
+            .collect::<FuturesUnordered<_>>();
+        while let Some(res) = jobs.next().await {
+            set.playlists.push(res?);
+        }
+    }
+
+    if displays.home_dashboard {
+        let _slot = instance.acquire_slot().await;
+        set.home_dashboard = instance.get_org_preferences().await?.home_dashboard_uid;
+    }
+    Ok(set)
+}
+
+async fn update_displays_on_instance(
+    instance: &GrafanaInstance,
+    update: DisplayUpdate<'_>,
+) -> Result<(), GSError> {
+    for (playlist, exists) in update.playlists {
+        let _slot = instance.acquire_slot().await;
+        instance.upsert_playlist(playlist, exists).await?;
+    }
+    if let Some(uid) = update.home_dashboard {
+        let _slot = instance.acquire_slot().await;
+        instance.set_home_dashboard(uid).await?;
+    }
+    Ok(())
+}
+
+async fn fetch_notification_set(
+    instance: &GrafanaInstance,
+    with_policies: bool,
+) -> Result<NotificationSet, GSError> {
+    let _slot = instance.acquire_slot().await;
+    Ok(NotificationSet {
+        contact_points: instance.get_contact_points().await?,
+        templates: instance.get_notification_templates().await?,
+        mute_timings: instance.get_mute_timings().await?,
+        policies: match with_policies {
+            true => Some(instance.get_notification_policies().await?),
+            false => None,
+        },
+    })
+}
+
+/// Tells configured secrets apart without keeping them around.
+fn secrets_digest(secrets: &HashMap<String, EncryptedCredential>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut settings = secrets.iter().collect::<Vec<_>>();
+    settings.sort_by_key(|(setting, _)| setting.as_str());
+    for (setting, secret) in settings {
+        (setting, secret.value()).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Writes one instance's outdated notification settings, dependencies first.
+///
+/// Secure contact point settings come back redacted from the source. They keep the target's
+/// own value, unless a value is configured for the target under `contact_point_secrets`.
+async fn update_notifications_on_instance(
+    instance: &GrafanaInstance,
+    update: NotificationUpdate<'_>,
+) -> Result<(), GSError> {
+    let base_url = instance.name();
+
+    for template in update.templates {
+        let _slot = instance.acquire_slot().await;
+        instance.put_notification_template(template).await?;
+    }
+    for (timing, exists) in update.mute_timings {
+        let _slot = instance.acquire_slot().await;
+        instance.upsert_mute_timing(timing, exists).await?;
+    }
+
+    for (source, uid) in update.contact_points {
+        let mut contact_point = ContactPoint {
+            uid: uid.unwrap_or_default().to_owned(),
+            ..source.clone()
+        };
+        if let Some(secrets) = instance.options().contact_point_secrets.get(&source.name) {
+            for (setting, secret) in secrets {
+                contact_point
+                    .settings
+                    .insert(setting.clone(), secret.value().into());
             }
-            Err(e) => error!("{}: could not create folder '{name}': {e}", instance.base_url()),
         }
+
+        let redacted = contact_point.redacted_settings();
+        if uid.is_none() && !redacted.is_empty() {
+            warn!(
+                "{base_url}: not creating contact point '{}', no value configured for: {}",
+                source.name,
+                redacted.join(", ")
+            );
+            continue;
+        }
+
+        let _slot = instance.acquire_slot().await;
+        instance.upsert_contact_point(&contact_point, uid).await?;
     }
-    (instance.base_url().to_owned(), map)
+
+    if let Some(tree) = update.policies {
+        let _slot = instance.acquire_slot().await;
+        instance.put_notification_policies(&tree).await?;
+    }
+    Ok(())
 }
 
 async fn replicate_dashboards_on_instance(
     folder_map: Arc<FolderMap>,
     dashboards: Arc<Vec<(String, RwLock<Option<FullDashboard>>)>>,
+    target: TargetContext,
+    cfg: Arc<Config>,
     inst: GrafanaInstance,
 ) -> Result<(), GSError> {
-    let folders = match folder_map.get(inst.base_url()) {
+    let folders = match folder_map.get(inst.name()) {
         Some(f) => f,
         None => {
-            error!("{}: folder map missing (unauthorised?)", inst.base_url());
+            error!("{}: folder map missing (unauthorised?)", inst.name());
             return Ok(());
         }
     };
@@ -195,7 +1595,10 @@ async fn replicate_dashboards_on_instance(
     for (uid, guard) in dashboards.iter() {
         let inst = inst.clone();
         let folders = folders.clone();
+        let target = &target;
+        let cfg = &cfg;
         jobs.push(async move {
+            let _slot = inst.acquire_slot().await;
             let maybe_dashboard = guard.read().await;
             match &*maybe_dashboard {
                 Some(d) => {
@@ -203,10 +1606,14 @@ async fn replicate_dashboards_on_instance(
                         .as_deref()
                         .unwrap_or("");
                     let folder = folders.get(title);
-                    inst.import_dashboard(d, folder, true).await?;
+                    let local = prepare_import(&inst, d, target)?;
+                    if !check_dependencies(&inst, &local, target, cfg)? {
+                        return Ok(());
+                    }
+                    inst.import_dashboard(&local, folder, true).await?;
                 }
                 None => {
-                    debug!("{}: deleting dashboard '{uid}'", inst.base_url());
+                    debug!("{}: deleting dashboard '{uid}'", inst.name());
                     // TODO: Only truly delete once I deem this stable
                     // inst.delete_dashboard(uid).await?;
                 }
@@ -220,3 +1627,102 @@ async fn replicate_dashboards_on_instance(
     }
     Ok(())
 }
+
+/// The dashboard as it is imported onto `inst`: with the instance's own datasource UIDs
+/// and transformed.
+fn prepare_import(
+    inst: &GrafanaInstance,
+    dashboard: &FullDashboard,
+    target: &TargetContext,
+) -> Result<FullDashboard, GSError> {
+    let mut local = dashboard.clone();
+    target.datasources.to_local(&mut local);
+    inst.options().transforms.apply(&mut local.dashboard)?;
+    Ok(local)
+}
+
+/// The datasources and panel plugins a prepared dashboard needs, but `target` lacks.
+fn missing_dependencies(
+    dashboard: &FullDashboard,
+    target: &TargetContext,
+) -> (Vec<String>, Vec<String>) {
+    let missing_datasources = target.datasources.missing_datasources(dashboard);
+    let missing_plugins = match &target.panel_plugins {
+        None => vec![],
+        Some(installed) => {
+            let mut missing = dashboard
+                .dashboard
+                .panel_types()
+                .into_iter()
+                .filter(|t| *t != "row" && !installed.contains(*t))
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            missing.sort();
+            missing
+        }
+    };
+    (missing_datasources, missing_plugins)
+}
+
+/// Applies the missing dependency policies to a dashboard about to be imported onto `inst`.
+/// Returns whether the import should go ahead.
+fn check_dependencies(
+    inst: &GrafanaInstance,
+    dashboard: &FullDashboard,
+    target: &TargetContext,
+    cfg: &Config,
+) -> Result<bool, GSError> {
+    let (missing_datasources, missing_plugins) = missing_dependencies(dashboard, target);
+
+    Ok(apply_missing_policy(
+        inst,
+        dashboard,
+        "datasources",
+        missing_datasources,
+        cfg.missing_datasources,
+    )? && apply_missing_policy(
+        inst,
+        dashboard,
+        "panel plugins",
+        missing_plugins,
+        cfg.missing_panel_plugins,
+    )?)
+}
+
+fn apply_missing_policy(
+    inst: &GrafanaInstance,
+    dashboard: &FullDashboard,
+    kind: &'static str,
+    missing: Vec<String>,
+    policy: MissingDependencyPolicy,
+) -> Result<bool, GSError> {
+    if missing.is_empty() {
+        return Ok(true);
+    }
+
+    let missing = missing.join(", ");
+    let title = &dashboard.dashboard.title;
+    match policy {
+        MissingDependencyPolicy::Ignore => Ok(true),
+        MissingDependencyPolicy::Warn => {
+            warn!(
+                "{}: importing '{title}' although it needs missing {kind}: {missing}",
+                inst.name()
+            );
+            Ok(true)
+        }
+        MissingDependencyPolicy::Skip => {
+            warn!(
+                "{}: skipping '{title}', it needs missing {kind}: {missing}",
+                inst.name()
+            );
+            Ok(false)
+        }
+        MissingDependencyPolicy::Fail => Err(GSError::MissingDependencies(
+            inst.name().to_owned(),
+            title.clone(),
+            kind,
+            missing,
+        )),
+    }
+}
@@ -0,0 +1,58 @@
+use crate::error::GSError;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Tells when SIGTERM or SIGINT asked the process to stop. A second signal exits right away.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+
+    /* Constructors */
+
+    /// Takes over SIGTERM and SIGINT from now on.
+    pub fn listen() -> Result<Self, GSError> {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let (sender, requested) = watch::channel(false);
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            info!("Shutting down after the current cycle, signal again to exit immediately");
+            sender.send_replace(true);
+
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            warn!("Exiting immediately, a running cycle is left unfinished");
+            std::process::exit(1);
+        });
+
+        Ok(Self { requested })
+    }
+
+    /* Public API */
+
+    /// Resolves once shutdown was requested.
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        // the sender only goes away when the process exits
+        let _ = requested.wait_for(|r| *r).await;
+    }
+
+    /// Resolves `timeout` after shutdown was requested.
+    pub async fn deadline(&self, timeout: Duration) {
+        self.requested().await;
+        tokio::time::sleep(timeout).await;
+    }
+}
@@ -0,0 +1,447 @@
+use crate::api::dashboards::FullDashboardData;
+use crate::error::GSError;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+// Stands in for everything a transformation may have changed while comparing copies
+const MASK: &str = "\u{0}grafana-sync-mask\u{0}";
+
+/// One step of an instance's `transforms` list as written in the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformConfig {
+    Set {
+        pointer: String,
+        value: Value,
+    },
+    Delete {
+        pointer: String,
+    },
+    DeletePanels {
+        #[serde(default)]
+        ids: Vec<u64>,
+        #[serde(default)]
+        title: Option<String>,
+    },
+    Replace {
+        pattern: String,
+        with: String,
+        #[serde(default)]
+        pointer: Option<String>,
+    },
+    AddTags {
+        tags: Vec<String>,
+    },
+    RemoveTags {
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Transform {
+    Set {
+        pointer: String,
+        value: Value,
+    },
+    Delete {
+        pointer: String,
+    },
+    // panels, also inside rows, matching by id or title
+    DeletePanels {
+        ids: Vec<u64>,
+        title: Option<Regex>,
+    },
+    Replace {
+        pattern: Regex,
+        with: String,
+        pointer: Option<String>,
+    },
+    AddTags(Vec<String>),
+    RemoveTags(Vec<String>),
+}
+
+/// Adjustments made to every dashboard before it is imported onto an instance.
+///
+/// Transformations can't generally be undone, so instead of inverting them the copies are
+/// compared through [`Pipeline::mask`], which blanks out everything any of them may touch.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    steps: Vec<Transform>,
+}
+
+impl Pipeline {
+    pub fn compile(configs: &[TransformConfig]) -> Result<Self, GSError> {
+        let steps = configs
+            .iter()
+            .map(|config| {
+                Ok(match config.clone() {
+                    TransformConfig::Set { pointer, value } => Transform::Set {
+                        pointer: check_pointer(pointer)?,
+                        value,
+                    },
+                    TransformConfig::Delete { pointer } => Transform::Delete {
+                        pointer: check_delete_pointer(pointer)?,
+                    },
+                    TransformConfig::DeletePanels { ids, title } => {
+                        if ids.is_empty() && title.is_none() {
+                            return Err(GSError::InvalidTransform(
+                                "delete_panels needs ids or a title".to_owned(),
+                            ));
+                        }
+                        Transform::DeletePanels {
+                            ids,
+                            title: title
+                                .map(|t| Regex::new(&t))
+                                .transpose()
+                                .map_err(|e| GSError::InvalidTransform(e.to_string()))?,
+                        }
+                    }
+                    TransformConfig::Replace {
+                        pattern,
+                        with,
+                        pointer,
+                    } => Transform::Replace {
+                        pattern: Regex::new(&pattern)
+                            .map_err(|e| GSError::InvalidTransform(e.to_string()))?,
+                        with,
+                        pointer: pointer.map(check_pointer).transpose()?,
+                    },
+                    TransformConfig::AddTags { tags } => Transform::AddTags(tags),
+                    TransformConfig::RemoveTags { tags } => Transform::RemoveTags(tags),
+                })
+            })
+            .collect::<Result<Vec<_>, GSError>>()?;
+
+        Ok(Self { steps })
+    }
+
+    /// Combines the steps of several pipelines, used to build the mask across all instances.
+    pub fn merged<'a>(pipelines: impl IntoIterator<Item = &'a Pipeline>) -> Self {
+        Self {
+            steps: pipelines
+                .into_iter()
+                .flat_map(|p| p.steps.iter().cloned())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Applies all steps in order.
+    pub fn apply(&self, dashboard: &mut FullDashboardData) -> Result<(), GSError> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+
+        let mut value = serde_json::to_value(&*dashboard)?;
+        self.for_each_step(&mut value, |step, value| match step {
+            Transform::Set { pointer, value: v } => set_pointer(value, pointer, v.clone()),
+            Transform::Delete { pointer } => {
+                delete_pointer(value, pointer);
+            }
+            Transform::DeletePanels { ids, title } => delete_panels(value, ids, title.as_ref()),
+            Transform::Replace {
+                pattern,
+                with,
+                pointer,
+            } => replace_strings(value, pointer.as_deref(), |s| {
+                pattern.replace_all(s, with.as_str()).into_owned()
+            }),
+            Transform::AddTags(tags) => edit_tags(value, |t| {
+                for tag in tags {
+                    if !t.contains(tag) {
+                        t.push(tag.clone());
+                    }
+                }
+            }),
+            Transform::RemoveTags(tags) => {
+                edit_tags(value, |t| t.retain(|tag| !tags.contains(tag)))
+            }
+        });
+
+        *dashboard = serde_json::from_value(value)
+            .map_err(|e| GSError::InvalidTransform(format!("result is no valid dashboard: {e}")))?;
+        Ok(())
+    }
+
+    /// Blanks out whatever the steps would change in a dashboard model, so that a transformed
+    /// and an untransformed copy of the same dashboard look alike.
+    pub fn mask(&self, value: &mut Value) {
+        self.for_each_step(value, |step, value| match step {
+            Transform::Set { pointer, .. } => set_pointer(value, pointer, Value::Null),
+            Transform::Delete { pointer } => {
+                delete_pointer(value, pointer);
+            }
+            // deleting by predicate again is a no-op on copies it already ran on
+            Transform::DeletePanels { ids, title } => delete_panels(value, ids, title.as_ref()),
+            Transform::Replace {
+                pattern,
+                with,
+                pointer,
+            } => replace_strings(value, pointer.as_deref(), |s| mask_replaced(s, pattern, with)),
+            Transform::AddTags(tags) | Transform::RemoveTags(tags) => {
+                edit_tags(value, |t| t.retain(|tag| !tags.contains(tag)))
+            }
+        });
+    }
+
+    fn for_each_step(&self, value: &mut Value, mut step_fn: impl FnMut(&Transform, &mut Value)) {
+        for step in &self.steps {
+            step_fn(step, value);
+        }
+    }
+}
+
+/// Masks both what `pattern` matches and the `with` text a replacement already put in, so
+/// that a string and its replaced version mask alike. The pattern only runs on the text
+/// between earlier masks and `with`, and runs of masks collapse into one: a pattern matching
+/// the empty string, like `^(\[LAB\] )?`, would otherwise add another mask on every pass.
+fn mask_replaced(s: &str, pattern: &Regex, with: &str) -> String {
+    let literal = !with.is_empty() && !with.contains('$');
+    let masked = s
+        .split(MASK)
+        .map(|part| {
+            let pieces: Vec<&str> = match literal {
+                true => part.split(with).collect(),
+                false => vec![part],
+            };
+            pieces
+                .into_iter()
+                .map(|piece| pattern.replace_all(piece, MASK).into_owned())
+                .collect::<Vec<_>>()
+                .join(MASK)
+        })
+        .collect::<Vec<_>>()
+        .join(MASK);
+
+    let mut collapsed = String::with_capacity(masked.len());
+    let mut rest = masked.as_str();
+    while let Some(at) = rest.find(MASK) {
+        collapsed.push_str(&rest[..at]);
+        collapsed.push_str(MASK);
+        rest = rest[at..].trim_start_matches(MASK);
+    }
+    collapsed.push_str(rest);
+    collapsed
+}
+
+fn check_pointer(pointer: String) -> Result<String, GSError> {
+    if pointer.is_empty() || !pointer.starts_with('/') {
+        return Err(GSError::InvalidTransform(format!(
+            "\"{pointer}\" is no JSON pointer into the dashboard"
+        )));
+    }
+    Ok(pointer)
+}
+
+/// Array elements can't be deleted by index: on a copy the deletion already ran on, the
+/// same index points at another element, so masking would never make the copies equal.
+fn check_delete_pointer(pointer: String) -> Result<String, GSError> {
+    let pointer = check_pointer(pointer)?;
+    if pointer.rsplit('/').next().is_some_and(|key| key.parse::<usize>().is_ok()) {
+        return Err(GSError::InvalidTransform(format!(
+            "\"{pointer}\" deletes by array index, use delete_panels to remove panels"
+        )));
+    }
+    Ok(pointer)
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Sets the value at `pointer`, creating the last key if its parent exists.
+fn set_pointer(root: &mut Value, pointer: &str, new: Value) {
+    if let Some(target) = root.pointer_mut(pointer) {
+        *target = new;
+        return;
+    }
+    let Some((parent, key)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    match root.pointer_mut(parent) {
+        Some(Value::Object(obj)) => {
+            obj.insert(unescape(key), new);
+        }
+        Some(Value::Array(items)) if key == "-" => items.push(new),
+        _ => {}
+    }
+}
+
+fn delete_pointer(root: &mut Value, pointer: &str) -> Option<Value> {
+    let (parent, key) = pointer.rsplit_once('/')?;
+    match root.pointer_mut(parent)? {
+        Value::Object(obj) => obj.remove(&unescape(key)),
+        Value::Array(items) => {
+            let index = key.parse::<usize>().ok().filter(|&i| i < items.len())?;
+            Some(items.remove(index))
+        }
+        _ => None,
+    }
+}
+
+fn delete_panels(root: &mut Value, ids: &[u64], title: Option<&Regex>) {
+    fn walk(panels: &mut Vec<Value>, matches: &impl Fn(&Value) -> bool) {
+        panels.retain(|panel| !matches(panel));
+        for panel in panels {
+            if let Some(Value::Array(nested)) = panel.get_mut("panels") {
+                walk(nested, matches);
+            }
+        }
+    }
+
+    let matches = |panel: &Value| {
+        panel
+            .get("id")
+            .and_then(Value::as_u64)
+            .is_some_and(|id| ids.contains(&id))
+            || title.is_some_and(|t| {
+                panel
+                    .get("title")
+                    .and_then(Value::as_str)
+                    .is_some_and(|s| t.is_match(s))
+            })
+    };
+    if let Some(Value::Array(panels)) = root.get_mut("panels") {
+        walk(panels, &matches);
+    }
+}
+
+fn replace_strings(root: &mut Value, pointer: Option<&str>, replace: impl Fn(&str) -> String + Copy) {
+    let target = match pointer {
+        Some(pointer) => root.pointer_mut(pointer),
+        None => Some(root),
+    };
+    if let Some(target) = target {
+        replace_all_strings(target, replace);
+    }
+}
+
+fn replace_all_strings(value: &mut Value, replace: impl Fn(&str) -> String + Copy) {
+    match value {
+        Value::String(s) => *s = replace(s),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|v| replace_all_strings(v, replace)),
+        Value::Object(obj) => obj
+            .values_mut()
+            .for_each(|v| replace_all_strings(v, replace)),
+        _ => {}
+    }
+}
+
+fn edit_tags(root: &mut Value, edit: impl FnOnce(&mut Vec<String>)) {
+    let Some(Value::Array(tags)) = root.get_mut("tags") else {
+        return;
+    };
+    let mut list: Vec<String> = tags
+        .iter()
+        .filter_map(|t| t.as_str().map(str::to_owned))
+        .collect();
+    edit(&mut list);
+    *tags = list.into_iter().map(Value::String).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pipeline(steps: Value) -> Pipeline {
+        Pipeline::compile(&serde_json::from_value::<Vec<TransformConfig>>(steps).unwrap()).unwrap()
+    }
+
+    fn dashboard(title: &str, tags: &[&str]) -> FullDashboardData {
+        FullDashboardData {
+            title: title.to_owned(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            panels: vec![
+                json!({"id": 1, "title": "Links", "description": "see https://grafana.example.de"}),
+                json!({"id": 3, "title": "Errors"}),
+                json!({"id": 7, "title": "Load (prod only)"}),
+            ],
+            templating: json!({"list": [{"name": "env", "current": {"text": "prod"}}]}),
+            ..Default::default()
+        }
+    }
+
+    /// Asserts that the original and its transformed copy compare equal through the mask.
+    fn assert_masks_alike(pipeline: &Pipeline, original: FullDashboardData) {
+        let mut transformed = original.clone();
+        pipeline.apply(&mut transformed).unwrap();
+        assert_ne!(transformed, original, "the pipeline should change the dashboard");
+
+        let masked = |d: &FullDashboardData| {
+            let mut value = serde_json::to_value(d).unwrap();
+            pipeline.mask(&mut value);
+            value
+        };
+        assert_eq!(masked(&original), masked(&transformed));
+        // transforming again changes nothing the mask doesn't cover
+        let mut twice = transformed.clone();
+        pipeline.apply(&mut twice).unwrap();
+        assert_eq!(masked(&twice), masked(&transformed));
+    }
+
+    fn readme_pipeline() -> Pipeline {
+        pipeline(json!([
+            {"op": "replace", "pointer": "/title", "pattern": "^(\\[LAB\\] )?", "with": "[LAB] "},
+            {
+                "op": "replace",
+                "pattern": "grafana\\.example\\.de",
+                "with": "grafana-lab.example.de",
+            },
+            {"op": "set", "pointer": "/templating/list/0/current", "value": {"text": "lab"}},
+            {"op": "delete", "pointer": "/description"},
+            {"op": "delete_panels", "ids": [3], "title": "\\(prod only\\)$"},
+            {"op": "add_tags", "tags": ["LAB"]},
+            {"op": "remove_tags", "tags": ["Production"]},
+        ]))
+    }
+
+    #[test]
+    fn optional_prefix_masks_alike() {
+        let prefix = pipeline(json!([
+            {"op": "replace", "pointer": "/title", "pattern": "^(\\[LAB\\] )?", "with": "[LAB] "},
+        ]));
+        assert_masks_alike(&prefix, dashboard("Foo", &[]));
+
+        let mut value = json!({"title": "Foo"});
+        let mut prefixed = json!({"title": "[LAB] Foo"});
+        prefix.mask(&mut value);
+        prefix.mask(&mut prefixed);
+        assert_eq!(value, prefixed);
+    }
+
+    #[test]
+    fn readme_examples_mask_alike() {
+        let mut original = dashboard("Foo", &["Production", "team"]);
+        original.extra.insert("description".to_owned(), json!("prod"));
+        assert_masks_alike(&readme_pipeline(), original);
+    }
+
+    #[test]
+    fn other_changes_still_differ() {
+        let readme = readme_pipeline();
+        let masked = |d: &FullDashboardData| {
+            let mut value = serde_json::to_value(d).unwrap();
+            readme.mask(&mut value);
+            value
+        };
+        assert_ne!(masked(&dashboard("Foo", &[])), masked(&dashboard("Bar", &[])));
+        assert_ne!(masked(&dashboard("Foo", &[])), masked(&dashboard("Foo", &["team"])));
+    }
+
+    #[test]
+    fn rejects_deleting_by_index() {
+        assert!(Pipeline::compile(&[TransformConfig::Delete { pointer: "/panels/0".to_owned() }])
+            .is_err());
+    }
+}
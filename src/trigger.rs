@@ -0,0 +1,337 @@
+use crate::encrypted_cred::EncryptedCredential;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::AUTHORIZATION;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(5);
+// Shorter secrets can be guessed over the network
+pub const MIN_SECRET_LEN: usize = 16;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// An HTTP endpoint which runs cycles on demand.
+#[derive(Debug, Clone)]
+pub struct TriggerSettings {
+    pub listen: SocketAddr,
+    pub secret: EncryptedCredential,
+    // triggers arriving within this time after the first one are handled together
+    pub debounce: Duration,
+}
+
+impl PartialEq for TriggerSettings {
+    fn eq(&self, other: &Self) -> bool {
+        self.listen == other.listen
+            && self.debounce == other.debounce
+            && self.secret.value() == other.secret.value()
+    }
+}
+
+/// What a trigger asks to sync.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerScope {
+    All,
+    Uids(BTreeSet<String>),
+}
+
+impl TriggerScope {
+    fn merge(&mut self, other: TriggerScope) {
+        match (self, other) {
+            (TriggerScope::Uids(uids), TriggerScope::Uids(more)) => uids.extend(more),
+            (scope, _) => *scope = TriggerScope::All,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TriggerPayload {
+    #[serde(default)]
+    uids: Vec<String>,
+    // as sent by Grafana's webhook contact point
+    #[serde(default)]
+    alerts: Vec<AlertPayload>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertPayload {
+    #[serde(default, rename = "dashboardURL")]
+    dashboard_url: Option<String>,
+}
+
+impl TriggerPayload {
+    /// Anything but an explicit list of dashboards, e.g. a CI pipeline's payload, syncs all.
+    fn scope(body: &[u8]) -> TriggerScope {
+        let Ok(payload) = serde_json::from_slice::<TriggerPayload>(body) else {
+            return TriggerScope::All;
+        };
+        let uids = payload
+            .uids
+            .into_iter()
+            .chain(
+                payload
+                    .alerts
+                    .iter()
+                    .filter_map(|a| a.dashboard_url.as_deref())
+                    .filter_map(dashboard_uid_of_url),
+            )
+            .collect::<BTreeSet<_>>();
+        match uids.is_empty() {
+            true => TriggerScope::All,
+            false => TriggerScope::Uids(uids),
+        }
+    }
+}
+
+/// `https://grafana.example.de/d/<uid>/<slug>?...` -> `<uid>`
+fn dashboard_uid_of_url(url: &str) -> Option<String> {
+    let (_, path) = url.split_once("/d/")?;
+    let uid = path.split(['/', '?', '#']).next()?;
+    (!uid.is_empty()).then(|| uid.to_owned())
+}
+
+/// Triggers received but not handled yet, merged into one.
+#[derive(Debug, Clone, Default)]
+pub struct TriggerQueue {
+    pending: Arc<Mutex<Option<TriggerScope>>>,
+    arrived: Arc<Notify>,
+}
+
+impl TriggerQueue {
+
+    /* Public API */
+
+    pub fn push(&self, scope: TriggerScope) {
+        let mut pending = self.pending.lock().expect("trigger lock is never poisoned");
+        match pending.as_mut() {
+            Some(queued) => queued.merge(scope),
+            None => *pending = Some(scope),
+        }
+        self.arrived.notify_one();
+    }
+
+    /// Waits for a trigger, then `debounce` long for more, and takes them all.
+    /// Cancel safe: the triggers stay queued.
+    pub async fn next(&self, debounce: Duration) -> TriggerScope {
+        loop {
+            while !self.is_pending() {
+                self.arrived.notified().await;
+            }
+            tokio::time::sleep(debounce).await;
+
+            let taken = self
+                .pending
+                .lock()
+                .expect("trigger lock is never poisoned")
+                .take();
+            if let Some(scope) = taken {
+                return scope;
+            }
+        }
+    }
+
+    /// Drops the queued triggers, e.g. because a full cycle covers them.
+    pub fn clear(&self) {
+        self.pending
+            .lock()
+            .expect("trigger lock is never poisoned")
+            .take();
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending
+            .lock()
+            .expect("trigger lock is never poisoned")
+            .is_some()
+    }
+}
+
+/// Answers `POST /trigger` until the process exits.
+pub async fn serve(listener: TcpListener, settings: TriggerSettings, queue: TriggerQueue) {
+    let settings = Arc::new(settings);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Could not accept a trigger connection: {e}");
+                continue;
+            }
+        };
+
+        let settings = settings.clone();
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            let service = service_fn(|request| handle(request, peer, &settings, &queue));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Trigger connection from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    request: Request<Incoming>,
+    peer: SocketAddr,
+    settings: &TriggerSettings,
+    queue: &TriggerQueue,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path() != "/trigger" {
+        return Ok(reply(StatusCode::NOT_FOUND, "not found"));
+    }
+    if request.method() != Method::POST {
+        return Ok(reply(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only POST is allowed",
+        ));
+    }
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| is_authorized(value, &settings.secret.value()));
+    if !authorized {
+        warn!("Rejected a trigger from {peer} without the shared secret");
+        return Ok(reply(StatusCode::UNAUTHORIZED, "unauthorized"));
+    }
+
+    let body = match Limited::new(request.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            debug!("Could not read trigger from {peer}: {e}");
+            return Ok(reply(StatusCode::BAD_REQUEST, "unreadable body"));
+        }
+    };
+
+    let scope = TriggerPayload::scope(&body);
+    match &scope {
+        TriggerScope::All => info!("Triggered by {peer}"),
+        TriggerScope::Uids(uids) => info!(
+            "Triggered by {peer} for {}",
+            uids.iter().cloned().collect::<Vec<_>>().join(", ")
+        ),
+    }
+    queue.push(scope);
+
+    Ok(reply(StatusCode::ACCEPTED, "queued"))
+}
+
+/// Accepts the secret as bearer token, or as basic auth password with any user name.
+fn is_authorized(authorization: &str, secret: &str) -> bool {
+    let presented = match authorization.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.to_owned(),
+        Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+            let Some(decoded) = BASE64
+                .decode(credentials)
+                .ok()
+                .and_then(|d| String::from_utf8(d).ok())
+            else {
+                return false;
+            };
+            match decoded.split_once(':') {
+                Some((_, password)) => password.to_owned(),
+                None => return false,
+            }
+        }
+        _ => return false,
+    };
+
+    // compares in constant time, so that the secret can't be guessed byte by byte
+    presented.len() == secret.len()
+        && presented
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn reply(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let body = serde_json::json!({ "status": message }).to_string();
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .expect("static response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef";
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+    }
+
+    #[test]
+    fn accepts_the_secret_as_bearer_token_or_basic_password() {
+        assert!(is_authorized(&format!("Bearer {SECRET}"), SECRET));
+        assert!(is_authorized(&format!("bearer {SECRET}"), SECRET));
+        assert!(is_authorized(&basic("ci", SECRET), SECRET));
+        assert!(is_authorized(&basic("", SECRET), SECRET));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert!(!is_authorized(&format!("Bearer {SECRET}x"), SECRET));
+        assert!(!is_authorized(&format!("Bearer {}", &SECRET[1..]), SECRET));
+        assert!(!is_authorized("Bearer ", SECRET));
+        assert!(!is_authorized(&basic(SECRET, "wrong"), SECRET));
+        assert!(!is_authorized("Basic not-base64!", SECRET));
+        assert!(!is_authorized(&format!("Basic {}", BASE64.encode(SECRET)), SECRET));
+        assert!(!is_authorized(&format!("Token {SECRET}"), SECRET));
+        assert!(!is_authorized(SECRET, SECRET));
+    }
+
+    #[test]
+    fn takes_the_uid_from_dashboard_urls() {
+        let uid = |url| dashboard_uid_of_url(url);
+        assert_eq!(uid("https://g.example/d/abc/slug?orgId=1"), Some("abc".to_owned()));
+        assert_eq!(uid("https://g.example/d/abc?from=now-1h"), Some("abc".to_owned()));
+        assert_eq!(uid("https://g.example/grafana/d/abc#panel"), Some("abc".to_owned()));
+        assert_eq!(uid("https://g.example/d/"), None);
+        assert_eq!(uid("https://g.example/alerting/list"), None);
+    }
+
+    #[test]
+    fn scopes_listed_and_alerted_dashboards() {
+        let body = br#"{
+            "uids": ["b"],
+            "alerts": [
+                {"dashboardURL": "https://g.example/d/a/slug"},
+                {"dashboardURL": ""},
+                {}
+            ]
+        }"#;
+        let expected = ["a", "b"].map(String::from).into_iter().collect();
+        assert_eq!(TriggerPayload::scope(body), TriggerScope::Uids(expected));
+    }
+
+    #[test]
+    fn scopes_everything_without_a_dashboard_list() {
+        assert_eq!(TriggerPayload::scope(b""), TriggerScope::All);
+        assert_eq!(TriggerPayload::scope(b"not json"), TriggerScope::All);
+        assert_eq!(TriggerPayload::scope(br#"{"ref": "main"}"#), TriggerScope::All);
+        assert_eq!(TriggerPayload::scope(br#"{"uids": []}"#), TriggerScope::All);
+    }
+}